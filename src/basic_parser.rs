@@ -18,227 +18,951 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-#[derive(Clone)]
-pub struct State {
-    s: String,
+//! Parser combinators over a `State`.
+//!
+//! The functions and methods here replace the former matcher macros:
+//!
+//! | Macro                     | Combinator                                 |
+//! |---------------------------|--------------------------------------------|
+//! | `chr!(c)`                 | `chr(c)`                                   |
+//! | `chr!(a, b)`              | `chr_range(a, b)`                          |
+//! | `chr_if!(pred)`           | `chr_if(pred)`                             |
+//! | `seq!(a, b, c)`           | `a.then(b).then(c)` or `seq(a, seq(b, c))` |
+//! | `alt!(a, b, c)`           | `a.or(b).or(c)` or `alt(a, alt(b, c))`     |
+//! | `opt!(m)`, `rep!(m, '?')` | `opt(m)` or `m.opt()`                      |
+//! | `any!(m)`, `rep!(m, '*')` | `many0(m)` or `m.many0()`                  |
+//! | `rep!(m, '+')`            | `many1(m)` or `m.many1()`                  |
+//!
+//! `seq` and `alt` take two parsers, so longer sequences and alternatives
+//! are chained. Sequences nest their values in pairs, and `recognize`
+//! turns any of them into the matched slice when only that is needed.
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A position inside the input being parsed. It only borrows the input, so
+/// it is cheap to copy around when backtracking.
+#[derive(Clone, Copy)]
+pub struct State<'a> {
+    input: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
-impl State {
-    pub fn from_string(s: &str) -> State {
+impl<'a> State<'a> {
+    /// Start of `input`, at line 1, column 1
+    pub fn from_string(input: &'a str) -> State<'a> {
         State {
-            s: s.to_string(),
+            input,
+            offset: 0,
+            line: 1,
+            column: 1,
         }
     }
 
-    pub fn display(&self) -> &str {
-        &self.s[..]
+    /// The input left to parse
+    pub fn display(&self) -> &'a str {
+        &self.input[self.offset..]
     }
 
+    /// Next character, or `'\0'` at the end of the input
     pub fn peek(&self) -> char {
-        match self.s.chars().next() {
-            Some(c) => c,
-            None => '\0',
-        }
+        self.display().chars().next().unwrap_or('\0')
     }
 
-    pub fn peek_many(&self, n: usize) -> &str {
-        if n > self.s.len() {
-            return "";
+    /// Returns the next `n` characters, or an empty string if there are fewer
+    pub fn peek_many(&self, n: usize) -> &'a str {
+        match self.byte_len(n) {
+            Some(len) => &self.display()[..len],
+            None => "",
         }
-        &self.s[..n]
     }
 
-    pub fn read(&self, n: usize) -> State {
+    /// Consumes the next `n` characters, stopping at the end of the input
+    pub fn read(&self, n: usize) -> State<'a> {
+        let rest = self.display();
+        let n = self.byte_len(n).unwrap_or(rest.len());
+        let (mut line, mut column) = (self.line, self.column);
+        for c in rest[..n].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
         State {
-            s: self.s[n..].to_string(),
+            input: self.input,
+            offset: self.offset + n,
+            line,
+            column,
         }
     }
 
+    /// Whether the whole input was consumed
     pub fn complete(&self) -> bool {
-        self.s.len() == 0
+        self.offset == self.input.len()
+    }
+
+    /// The input between this state and `end`
+    pub fn slice_to(&self, end: &State) -> &'a str {
+        &self.input[self.offset..end.offset]
+    }
+
+    // Length in bytes of the next `n` characters, None if there are fewer
+    fn byte_len(&self, n: usize) -> Option<usize> {
+        let mut chars = self.display().chars();
+        let mut len = 0;
+        for _ in 0..n {
+            len += chars.next()?.len_utf8();
+        }
+        Some(len)
+    }
+
+    /// Byte offset from the beginning of the input
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Line of the next character, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column of the next character, starting at 1, in characters
+    pub fn column(&self) -> usize {
+        self.column
     }
 }
 
 
-pub struct Transition<T> where T: Fn(&State) -> Option<State>, {
-    func: T,
+/// A point of the input, as reported in nodes and errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Byte offset from the beginning of the input
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-impl<T> Transition<T> where T: Fn(&State) -> Option<State>, {
-    pub fn new(func: T) -> Transition<T> {
-        Transition {
-            func,
+impl<'a> From<&State<'a>> for Position {
+    fn from(state: &State<'a>) -> Position {
+        Position {
+            offset: state.offset(),
+            line: state.line(),
+            column: state.column(),
         }
     }
+}
+
+/// Region of the input a node or an error refers to, `end` excluded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
 
-    pub fn matches(&self, state: &State) -> Option<State> {
-        (self.func)(state)
+impl Span {
+    pub(crate) fn new(start: &State, end: &State) -> Span {
+        Span {
+            start: Position::from(start),
+            end: Position::from(end),
+        }
+    }
+
+    // Smallest span covering both `self` and `other`
+    pub(crate) fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
     }
 }
 
-macro_rules! chr {
-    ($c:expr) => {
-        chr!($c, $c)
-    };
-    ($c1:expr, $c2:expr) => {
-        {
-            let trans = Transition::new(move |state| {
-                let curr = state.peek();
-                if curr >= $c1 && curr <= $c2 {
-                    return Some(state.read(1));
-                }
-                None
-            });
-            trans
+/// Why the input could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Covers the offending character, empty at the end of the input
+    pub span: Span,
+    /// None when the end of the input was reached
+    pub found: Option<char>,
+    /// Labels of whatever would have been accepted at `span`
+    pub expected: Vec<String>,
+    /// Why well-formed input was rejected, in place of what was expected
+    pub message: Option<String>,
+}
+
+impl ParseError {
+    fn new(state: &State, label: Option<&str>) -> ParseError {
+        ParseError {
+            span: Span::new(state, &state.read(1)),
+            found: match state.complete() {
+                true => None,
+                false => Some(state.peek()),
+            },
+            expected: label.into_iter().map(String::from).collect(),
+            message: None,
         }
-    };
+    }
 }
 
-macro_rules! seq {
-    ($m1:expr, $($m2:expr),*) => {
-        {
-            let trans = Transition::new(move |state| {
-                let ret = $m1.matches(state);
-                if ret.is_none() {
-                    return None;
-                }
-                $(
-                    // Now match the other matchers
-                    let state = ret.unwrap();
-                    let ret = $m2.matches(&state);
-                    if ret.is_none() {
-                        return None;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(message) = &self.message {
+            return write!(f, "{} at {}:{}", message, self.span.start.line, self.span.start.column);
+        }
+        if self.expected.is_empty() {
+            match self.found {
+                Some(c) => write!(f, "unexpected {:?}", c)?,
+                None => write!(f, "unexpected end of input")?,
+            }
+            return write!(f, " at {}:{}", self.span.start.line, self.span.start.column);
+        }
+        write!(f, "expected ")?;
+        for (i, label) in self.expected.iter().enumerate() {
+            if i > 0 {
+                let sep = if i + 1 == self.expected.len() { " or " } else { ", " };
+                write!(f, "{}", sep)?;
+            }
+            write!(f, "{}", label)?;
+        }
+        write!(f, " at {}:{}", self.span.start.line, self.span.start.column)?;
+        match self.found {
+            Some(c) => write!(f, ", found {:?}", c),
+            None => write!(f, ", found end of input"),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// Furthest position where a parser failed, with everything that was
+/// expected there. Failures before it are of no interest, as the input
+/// matched further along.
+#[derive(Debug)]
+pub struct Failure {
+    error: Option<ParseError>,
+    // Off when nobody is going to look at the failures
+    tracking: bool,
+}
+
+impl Failure {
+    /// Nothing failed yet
+    pub fn new() -> Failure {
+        Failure {
+            error: None,
+            tracking: true,
+        }
+    }
+
+    fn untracked() -> Failure {
+        Failure {
+            error: None,
+            tracking: false,
+        }
+    }
+
+    /// Records that `label` was expected at `state`
+    pub fn fail(&mut self, state: &State, label: &str) {
+        self.record(state, Some(label));
+    }
+
+    /// Records a failure at `state` without telling what was expected
+    pub fn unexpected(&mut self, state: &State) {
+        self.record(state, None);
+    }
+
+    /// Records that the input from `start` to `end` is well-formed but was
+    /// rejected, for `message`. Nothing else expected at `start` is
+    /// reported alongside, nor are failures inside the rejected input.
+    pub fn reject(&mut self, start: &State, end: &State, message: &str) {
+        if !self.tracking { return; }
+        if let Some(e) = &self.error {
+            let offset = e.span.start.offset;
+            if offset > start.offset() && offset >= end.offset() { return; }
+        }
+        let mut error = ParseError::new(start, None);
+        error.span = Span::new(start, end);
+        error.message = Some(message.to_string());
+        self.error = Some(error);
+    }
+
+    // Tracks the failures of a nested parser apart, to be merged back
+    fn nested(&self) -> Failure {
+        Failure {
+            error: None,
+            tracking: self.tracking,
+        }
+    }
+
+    fn merge(&mut self, other: Failure) {
+        let other = match other.error {
+            Some(other) => other,
+            None => return,
+        };
+        let offset = other.span.start.offset;
+        match self.error.as_mut() {
+            Some(e) if e.span.start.offset > offset => (),
+            Some(e) if e.span.start.offset == offset && other.message.is_some() => *e = other,
+            Some(e) if e.span.start.offset == offset && e.message.is_some() => (),
+            Some(e) if e.span.start.offset == offset => {
+                for label in other.expected {
+                    if !e.expected.contains(&label) {
+                        e.expected.push(label);
                     }
-                )*
-                ret
-            });
-            trans
-        }
-    };
-}
-
-macro_rules! opt {
-    ($m:expr) => {
-        {
-            let trans = Transition::new(move |state| {
-                let ret = $m.matches(state);
-                match ret {
-                    Some(_) => ret,
-                    None => Some(state.clone()),
                 }
-            });
-            trans
-        }
-    };
-}
-
-macro_rules! any {
-    ($m:expr) => {
-        {
-            let trans = Transition::new(move |state| {
-                let mut prev = state.clone();
-                loop {
-                    let ret = $m.matches(&prev);
-                    if ret.is_some() {
-                        prev = ret.unwrap();
-                    } else {
-                        return Some(prev);
+            },
+            _ => self.error = Some(other),
+        }
+    }
+
+    // Whether a failure at `state` would be recorded, so that labels are
+    // only built when needed
+    fn reaches(&self, state: &State) -> bool {
+        match &self.error {
+            Some(e) => self.tracking && e.span.start.offset <= state.offset(),
+            None => self.tracking,
+        }
+    }
+
+    fn record(&mut self, state: &State, label: Option<&str>) {
+        if !self.reaches(state) { return; }
+        match self.error.as_mut() {
+            Some(e) if e.span.start.offset == state.offset() => {
+                if e.message.is_some() { return; }
+                if let Some(label) = label {
+                    if !e.expected.iter().any(|l| l == label) {
+                        e.expected.push(label.to_string());
                     }
                 }
-            });
-            trans
-        }
-    };
-}
-
-macro_rules! rep {
-    ($matcher:expr, $times:expr) => {
-        {
-            let trans = Transition::new(move |state| {
-                match $times {
-                    '?' => opt!($matcher).matches(state),
-                    '+' => {
-                        let ret = $matcher.matches(state);
-                        match ret {
-                            Some(_) => any!($matcher).matches(&ret.unwrap()),
-                            None => None,
-                        }
-                    },
-                    '*' => any!($matcher).matches(state),
-                    _ => panic!("Unknown quantifier {}", $times),
-                }
-            });
-            trans
+            },
+            _ => self.error = Some(ParseError::new(state, label)),
+        }
+    }
+
+    // Number of labels expected at `state` so far, None when a failure was
+    // already recorded further
+    fn mark(&self, state: &State) -> Option<usize> {
+        match &self.error {
+            Some(e) if e.span.start.offset > state.offset() => None,
+            Some(e) if e.span.start.offset == state.offset() => Some(e.expected.len()),
+            _ => Some(0),
         }
-    };
+    }
+
+    // Replaces the labels expected at `state` since `mark` with `label`
+    fn relabel(&mut self, state: &State, mark: Option<usize>, label: &str) {
+        if let (Some(len), Some(e)) = (mark, self.error.as_mut()) {
+            if e.span.start.offset == state.offset() && e.message.is_none() {
+                e.expected.truncate(len);
+                self.record(state, Some(label));
+            }
+        }
+    }
+
+    /// The furthest failure, if anything failed
+    pub fn into_error(self) -> Option<ParseError> {
+        self.error
+    }
 }
 
-macro_rules! alt {
-    ($($m:expr),+) => {
-        {
-            let trans = Transition::new(move |state| {
-                $(
-                    let ret = $m.matches(state);
-                    if ret.is_some() {
-                        return ret;
-                    }
-                )*
-                None
-            });
-            trans
+impl Default for Failure {
+    fn default() -> Failure {
+        Failure::new()
+    }
+}
+
+/// The value built by a parser along with the state following it
+pub type Parsed<'a, O> = Option<(O, State<'a>)>;
+
+/// Anything that matches at a `State`, building a value out of what it
+/// matched. Parsers are combined with the methods below, so a grammar rule
+/// reads much like its definition.
+pub trait Parser<'a> {
+    type Output;
+
+    /// Parses at `state`, recording in `failure` where and why the parser or
+    /// any parser it is made of failed
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output>;
+
+    /// Only checks where the match ends. Combinators override it when they
+    /// can avoid building values.
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        self.parse_tracked(state, failure).map(|(_, next)| next)
+    }
+
+    /// Like `parse_tracked`, for when nobody looks at the failures
+    fn parse(&self, state: &State<'a>) -> Parsed<'a, Self::Output> {
+        self.parse_tracked(state, &mut Failure::untracked())
+    }
+
+    /// Like `matches_tracked`, for when nobody looks at the failures
+    fn matches(&self, state: &State<'a>) -> Option<State<'a>> {
+        self.matches_tracked(state, &mut Failure::untracked())
+    }
+
+    /// Parses the whole of `input`, reporting the furthest failure otherwise
+    fn run(&self, input: &'a str) -> Result<Self::Output, ParseError> {
+        let start = State::from_string(input);
+        let mut failure = Failure::new();
+        if let Some((value, end)) = self.parse_tracked(&start, &mut failure) {
+            if end.complete() { return Ok(value); }
+            failure.fail(&end, "end of input");
         }
+        // Only there for parsers not recording their failures
+        failure.unexpected(&start);
+        Err(failure.into_error().unwrap())
+    }
+
+    /// Same as `seq(self, next)`
+    fn then<P>(self, next: P) -> Then<Self, P>
+        where Self: Sized, P: Parser<'a>,
+    {
+        seq(self, next)
+    }
+
+    /// Same as `alt(self, other)`
+    fn or<P>(self, other: P) -> Or<Self, P>
+        where Self: Sized, P: Parser<'a, Output = Self::Output>,
+    {
+        alt(self, other)
+    }
+
+    /// Same as `opt(self)`
+    fn opt(self) -> Opt<Self> where Self: Sized {
+        opt(self)
+    }
+
+    /// Same as `many0(self)`
+    fn many0(self) -> Many0<Self> where Self: Sized {
+        many0(self)
+    }
+
+    /// Same as `many1(self)`
+    fn many1(self) -> Many1<Self> where Self: Sized {
+        many1(self)
+    }
+
+    /// Converts the value with `f`
+    fn map<U, F>(self, f: F) -> Map<Self, F>
+        where Self: Sized, F: Fn(Self::Output) -> U,
+    {
+        Map { parser: self, f }
+    }
+
+    /// Like `map`, but the conversion can reject the value, failing the match
+    fn and_then<U, F>(self, f: F) -> AndThen<Self, F>
+        where Self: Sized, F: Fn(Self::Output) -> Option<U>,
+    {
+        AndThen { parser: self, f }
+    }
+
+    /// Zero or more items separated by `sep`. A trailing separator is left
+    /// unconsumed.
+    fn sep_by<S>(self, sep: S) -> SepBy<Self, S>
+        where Self: Sized, S: Parser<'a>,
+    {
+        SepBy { parser: self, sep }
+    }
+
+    /// Replaces the value with the slice of input that was matched
+    fn recognize(self) -> Recognize<Self> where Self: Sized {
+        Recognize { parser: self }
+    }
+
+    /// Names the rule in error messages. Failing where it starts, `name` is
+    /// what was expected there instead of whatever the rule is made of.
+    fn label<N>(self, name: N) -> Label<Self>
+        where Self: Sized, N: Into<Cow<'static, str>>,
+    {
+        Label { parser: self, name: name.into() }
+    }
+
+    /// Keeps the rule out of error messages, for input such as whitespace
+    /// that is never what is missing
+    fn hidden(self) -> Hidden<Self> where Self: Sized {
+        Hidden { parser: self }
     }
 }
 
-///////////////////////////////////////////////////////////////////////
-// Complex macro
-///////////////////////////////////////////////////////////////////////
+impl<'a, P> Parser<'a> for &P where P: Parser<'a> + ?Sized {
+    type Output = P::Output;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, P::Output> {
+        (**self).parse_tracked(state, failure)
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        (**self).matches_tracked(state, failure)
+    }
+}
+
+/// A parser only recognizing input, written as a closure
+pub struct Transition<T> where T: for<'a> Fn(&State<'a>) -> Option<State<'a>>, {
+    func: T,
+}
+
+impl<T> Transition<T> where T: for<'a> Fn(&State<'a>) -> Option<State<'a>>, {
+    /// Wraps `func`, which returns where the match ends
+    pub fn new(func: T) -> Transition<T> {
+        Transition {
+            func,
+        }
+    }
+}
+
+impl<'a, T> Parser<'a> for Transition<T> where T: for<'b> Fn(&State<'b>) -> Option<State<'b>>, {
+    type Output = ();
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, ()> {
+        self.matches_tracked(state, failure).map(|next| ((), next))
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        let ret = (self.func)(state);
+        if ret.is_none() { failure.unexpected(state); }
+        ret
+    }
+}
+
+/// A parser building a value, written as a closure
+pub struct Combinator<O, F> {
+    func: F,
+    output: PhantomData<fn() -> O>,
+}
+
+impl<O, F> Combinator<O, F> where F: for<'a> Fn(&State<'a>) -> Parsed<'a, O>, {
+    /// Wraps `func`, which returns the value along with where the match ends
+    pub fn new(func: F) -> Combinator<O, F> {
+        Combinator {
+            func,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<'a, O, F> Parser<'a> for Combinator<O, F> where F: Fn(&State<'a>) -> Parsed<'a, O>, {
+    type Output = O;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, O> {
+        let ret = (self.func)(state);
+        if ret.is_none() { failure.unexpected(state); }
+        ret
+    }
+}
+
+/// Parser returned by `seq` and `Parser::then`
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<'a, A, B> Parser<'a> for Then<A, B> where A: Parser<'a>, B: Parser<'a>, {
+    type Output = (A::Output, B::Output);
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output> {
+        let (first, state) = self.first.parse_tracked(state, failure)?;
+        let (second, state) = self.second.parse_tracked(&state, failure)?;
+        Some(((first, second), state))
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        let state = self.first.matches_tracked(state, failure)?;
+        self.second.matches_tracked(&state, failure)
+    }
+}
+
+/// Failures of both alternatives end up in the same `Failure`, so their
+/// expected labels are merged when they fail at the same position
+pub struct Or<A, B> {
+    first: A,
+    second: B,
+}
 
-macro_rules! lower_letter {
-    () => {
-        chr!('a', 'z')
+impl<'a, A, B> Parser<'a> for Or<A, B> where A: Parser<'a>, B: Parser<'a, Output = A::Output>, {
+    type Output = A::Output;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, A::Output> {
+        self.first.parse_tracked(state, failure)
+            .or_else(|| self.second.parse_tracked(state, failure))
     }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        self.first.matches_tracked(state, failure)
+            .or_else(|| self.second.matches_tracked(state, failure))
+    }
+}
+
+/// Parser returned by `opt` and `Parser::opt`
+pub struct Opt<P> {
+    parser: P,
 }
 
-macro_rules! upper_letter {
-    () => {
-        chr!('A', 'Z')
+impl<'a, P> Parser<'a> for Opt<P> where P: Parser<'a>, {
+    type Output = Option<P::Output>;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output> {
+        match self.parser.parse_tracked(state, failure) {
+            Some((value, next)) => Some((Some(value), next)),
+            None => Some((None, *state)),
+        }
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        Some(self.parser.matches_tracked(state, failure).unwrap_or(*state))
+    }
+}
+
+// Applies `parser` until it fails, collecting the values. It also stops on
+// a match that consumes nothing, which would otherwise repeat forever.
+fn repeat<'a, P>(parser: &P, state: State<'a>, failure: &mut Failure, values: &mut Vec<P::Output>) -> State<'a>
+    where P: Parser<'a>,
+{
+    let mut prev = state;
+    while let Some((value, next)) = parser.parse_tracked(&prev, failure) {
+        if next.offset() == prev.offset() {
+            break;
+        }
+        values.push(value);
+        prev = next;
     }
+    prev
 }
 
-macro_rules! letter {
-    () => {
-        alt!(lower_letter!(), upper_letter!())
+// Like `repeat`, without building any value
+fn skip<'a, P>(parser: &P, state: State<'a>, failure: &mut Failure) -> State<'a> where P: Parser<'a>, {
+    let mut prev = state;
+    while let Some(next) = parser.matches_tracked(&prev, failure) {
+        if next.offset() == prev.offset() {
+            break;
+        }
+        prev = next;
     }
+    prev
+}
+
+/// Parser returned by `many0` and `Parser::many0`
+pub struct Many0<P> {
+    parser: P,
+}
+
+impl<'a, P> Parser<'a> for Many0<P> where P: Parser<'a>, {
+    type Output = Vec<P::Output>;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output> {
+        let mut values = Vec::new();
+        let end = repeat(&self.parser, *state, failure, &mut values);
+        Some((values, end))
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        Some(skip(&self.parser, *state, failure))
+    }
+}
+
+/// Parser returned by `many1` and `Parser::many1`
+pub struct Many1<P> {
+    parser: P,
+}
+
+impl<'a, P> Parser<'a> for Many1<P> where P: Parser<'a>, {
+    type Output = Vec<P::Output>;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output> {
+        let (first, next) = self.parser.parse_tracked(state, failure)?;
+        let mut values = vec![first];
+        let end = repeat(&self.parser, next, failure, &mut values);
+        Some((values, end))
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        let next = self.parser.matches_tracked(state, failure)?;
+        Some(skip(&self.parser, next, failure))
+    }
+}
+
+/// Parser returned by `Parser::map`
+pub struct Map<P, F> {
+    parser: P,
+    f: F,
+}
+
+impl<'a, P, F, U> Parser<'a> for Map<P, F> where P: Parser<'a>, F: Fn(P::Output) -> U, {
+    type Output = U;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, U> {
+        let (value, next) = self.parser.parse_tracked(state, failure)?;
+        Some(((self.f)(value), next))
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        self.parser.matches_tracked(state, failure)
+    }
+}
+
+/// Parser returned by `Parser::and_then`
+pub struct AndThen<P, F> {
+    parser: P,
+    f: F,
+}
+
+impl<'a, P, F, U> Parser<'a> for AndThen<P, F> where P: Parser<'a>, F: Fn(P::Output) -> Option<U>, {
+    type Output = U;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, U> {
+        let mut inner = failure.nested();
+        let (value, next) = match self.parser.parse_tracked(state, &mut inner) {
+            Some(ret) => ret,
+            None => {
+                failure.merge(inner);
+                return None;
+            },
+        };
+        match (self.f)(value) {
+            Some(value) => {
+                failure.merge(inner);
+                Some((value, next))
+            },
+            // The rejected input is wrong as a whole, so whatever its parser
+            // would have accepted past its start is beside the point
+            None => {
+                failure.unexpected(state);
+                None
+            },
+        }
+    }
+}
+
+/// Parser returned by `Parser::sep_by`
+pub struct SepBy<P, S> {
+    parser: P,
+    sep: S,
 }
 
-macro_rules! alpha {
-    () => {
-        alt!(letter!(), chr!('_'))
+impl<'a, P, S> Parser<'a> for SepBy<P, S> where P: Parser<'a>, S: Parser<'a>, {
+    type Output = Vec<P::Output>;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Self::Output> {
+        let (first, mut prev) = match self.parser.parse_tracked(state, failure) {
+            Some(ret) => ret,
+            None => return Some((Vec::new(), *state)),
+        };
+        let mut values = vec![first];
+        while let Some(sep) = self.sep.matches_tracked(&prev, failure) {
+            let (value, next) = match self.parser.parse_tracked(&sep, failure) {
+                Some(ret) => ret,
+                None => break,
+            };
+            values.push(value);
+            prev = next;
+        }
+        Some((values, prev))
     }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        let mut prev = match self.parser.matches_tracked(state, failure) {
+            Some(next) => next,
+            None => return Some(*state),
+        };
+        while let Some(sep) = self.sep.matches_tracked(&prev, failure) {
+            match self.parser.matches_tracked(&sep, failure) {
+                Some(next) => prev = next,
+                None => break,
+            }
+        }
+        Some(prev)
+    }
+}
+
+/// Parser returned by `Parser::recognize`
+pub struct Recognize<P> {
+    parser: P,
 }
 
-macro_rules! digit {
-    () => {
-        chr!('0', '9')
+impl<'a, P> Parser<'a> for Recognize<P> where P: Parser<'a>, {
+    type Output = &'a str;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, &'a str> {
+        let next = self.parser.matches_tracked(state, failure)?;
+        Some((state.slice_to(&next), next))
     }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        self.parser.matches_tracked(state, failure)
+    }
+}
+
+/// Parser returned by `Parser::label`
+pub struct Label<P> {
+    parser: P,
+    name: Cow<'static, str>,
 }
 
-macro_rules! letter_str {
-    () => {
-        rep!(letter!(), '+')
+// Failures past the start of the rule are more precise than its name, so
+// those are kept as they are
+impl<'a, P> Parser<'a> for Label<P> where P: Parser<'a>, {
+    type Output = P::Output;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, P::Output> {
+        let mark = failure.mark(state);
+        let ret = self.parser.parse_tracked(state, failure);
+        failure.relabel(state, mark, &self.name);
+        ret
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Option<State<'a>> {
+        let mark = failure.mark(state);
+        let ret = self.parser.matches_tracked(state, failure);
+        failure.relabel(state, mark, &self.name);
+        ret
     }
 }
 
-macro_rules! alpha_str {
-    () => {
-        rep!(alpha!(), '+')
+/// Parser returned by `Parser::hidden`
+pub struct Hidden<P> {
+    parser: P,
+}
+
+impl<'a, P> Parser<'a> for Hidden<P> where P: Parser<'a>, {
+    type Output = P::Output;
+
+    fn parse_tracked(&self, state: &State<'a>, _failure: &mut Failure) -> Parsed<'a, P::Output> {
+        self.parser.parse(state)
+    }
+
+    fn matches_tracked(&self, state: &State<'a>, _failure: &mut Failure) -> Option<State<'a>> {
+        self.parser.matches(state)
     }
 }
 
-macro_rules! alphanum_str {
-    () => {
-        rep!(alt!(alpha!(), digit!()), '+')
+struct CharRange {
+    first: char,
+    last: char,
+}
+
+impl<'a> Parser<'a> for CharRange {
+    type Output = char;
+
+    fn parse_tracked(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, char> {
+        let c = state.peek();
+        if !state.complete() && (self.first..=self.last).contains(&c) {
+            return Some((c, state.read(1)));
+        }
+        if failure.reaches(state) {
+            let label = match self.first == self.last {
+                true => format!("{:?}", self.first),
+                false => format!("{:?}..{:?}", self.first, self.last),
+            };
+            failure.fail(state, &label);
+        }
+        None
     }
 }
 
+/// Unlabeled, as nothing tells what `pred` accepts
+pub fn chr_if<F>(pred: F) -> impl for<'a> Parser<'a, Output = char> where F: Fn(char) -> bool, {
+    Combinator::new(move |state| {
+        let c = state.peek();
+        if !state.complete() && pred(c) {
+            return Some((c, state.read(1)));
+        }
+        None
+    })
+}
+
+/// Any character from `first` to `last`, both included
+pub fn chr_range(first: char, last: char) -> impl for<'a> Parser<'a, Output = char> {
+    CharRange { first, last }
+}
+
+/// Exactly `c`
+pub fn chr(c: char) -> impl for<'a> Parser<'a, Output = char> {
+    chr_range(c, c)
+}
+
+/// `first` followed by `second`, keeping both values
+pub fn seq<A, B>(first: A, second: B) -> Then<A, B> {
+    Then { first, second }
+}
+
+/// `first`, or `second` where `first` does not match
+pub fn alt<A, B>(first: A, second: B) -> Or<A, B> {
+    Or { first, second }
+}
+
+/// `parser` if it matches, None otherwise
+pub fn opt<P>(parser: P) -> Opt<P> {
+    Opt { parser }
+}
+
+/// `parser` as many times as it matches, possibly none
+pub fn many0<P>(parser: P) -> Many0<P> {
+    Many0 { parser }
+}
+
+/// `parser` as many times as it matches, at least once
+pub fn many1<P>(parser: P) -> Many1<P> {
+    Many1 { parser }
+}
+
+///////////////////////////////////////////////////////////////////////
+// Complex parsers
+///////////////////////////////////////////////////////////////////////
+
+/// An ASCII lowercase letter
+pub fn lower_letter() -> impl for<'a> Parser<'a, Output = char> {
+    chr_range('a', 'z').label("lowercase letter")
+}
+
+/// An ASCII uppercase letter
+pub fn upper_letter() -> impl for<'a> Parser<'a, Output = char> {
+    chr_range('A', 'Z').label("uppercase letter")
+}
+
+/// An ASCII letter
+pub fn letter() -> impl for<'a> Parser<'a, Output = char> {
+    alt(lower_letter(), upper_letter()).label("letter")
+}
+
+/// A character that can start a Unicode identifier
+pub fn unicode_letter() -> impl for<'a> Parser<'a, Output = char> {
+    chr_if(unicode_xid::UnicodeXID::is_xid_start).label("letter")
+}
+
+/// An ASCII letter or `_`
+pub fn alpha() -> impl for<'a> Parser<'a, Output = char> {
+    alt(letter(), chr('_'))
+}
+
+/// A Unicode letter or `_`
+pub fn unicode_alpha() -> impl for<'a> Parser<'a, Output = char> {
+    alt(unicode_letter(), chr('_'))
+}
+
+/// An ASCII digit
+pub fn digit() -> impl for<'a> Parser<'a, Output = char> {
+    chr_range('0', '9').label("digit")
+}
+
+/// One or more ASCII letters
+pub fn letter_str() -> impl for<'a> Parser<'a, Output = &'a str> {
+    many1(letter()).recognize()
+}
+
+/// One or more ASCII letters or `_`
+pub fn alpha_str() -> impl for<'a> Parser<'a, Output = &'a str> {
+    many1(alpha()).recognize()
+}
+
+/// One or more ASCII letters, digits or `_`
+pub fn alphanum_str() -> impl for<'a> Parser<'a, Output = &'a str> {
+    many1(alt(alpha(), digit())).recognize()
+}
+
+/// One or more characters that can continue a Unicode identifier
+pub fn unicode_alphanum_str() -> impl for<'a> Parser<'a, Output = &'a str> {
+    many1(chr_if(unicode_xid::UnicodeXID::is_xid_continue).label("letter or digit")).recognize()
+}
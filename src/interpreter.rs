@@ -18,34 +18,1078 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::parser::{Fixity, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    // Absence of a value, only equal to itself
+    Null,
+    List(Vec<Value>),
+    // Record whose fields are reached with dotted names, e.g. `$user.address`,
+    // or by key, e.g. `$headers["Content-Type"]`
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Null => "null",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Boolean(value)
+    }
+}
+
+impl<V: Into<Value>> From<Vec<V>> for Value {
+    fn from(value: Vec<V>) -> Value {
+        Value::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<V: Into<Value>> From<BTreeMap<String, V>> for Value {
+    fn from(value: BTreeMap<String, V>) -> Value {
+        Value::Map(value.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<V: Into<Value>> From<HashMap<String, V>> for Value {
+    fn from(value: HashMap<String, V>) -> Value {
+        Value::Map(value.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::String(v) => write!(f, "{:?}", v),
+            Value::Boolean(v) => write!(f, "{}", v),
+            Value::Null => write!(f, "null"),
+            Value::List(l) => {
+                write!(f, "[")?;
+                for (i, v) in l.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}: {}", k, v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    UnknownVariable { name: String, span: Span },
+    // `path` resolved to a map without `field`
+    MissingField { path: String, field: String, span: Span },
+    UnknownFunction { name: String, span: Span },
+    WrongArity { name: String, expected: usize, found: usize, span: Span },
+    // A host function reported a failure
+    FunctionFailed { name: String, message: String, span: Span },
+    TypeMismatch { expected: &'static str, found: &'static str, span: Span },
+    IndexOutOfBounds { index: i64, len: usize, span: Span },
+    DivisionByZero { span: Span },
+    Overflow { span: Span },
+}
+
+impl EvalError {
+    // The sub-expression that caused the error
+    pub fn span(&self) -> Span {
+        match self {
+            EvalError::UnknownVariable { span, .. } => *span,
+            EvalError::MissingField { span, .. } => *span,
+            EvalError::UnknownFunction { span, .. } => *span,
+            EvalError::WrongArity { span, .. } => *span,
+            EvalError::FunctionFailed { span, .. } => *span,
+            EvalError::TypeMismatch { span, .. } => *span,
+            EvalError::IndexOutOfBounds { span, .. } => *span,
+            EvalError::DivisionByZero { span } => *span,
+            EvalError::Overflow { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnknownVariable { name, .. } => write!(f, "unknown variable {}", name),
+            EvalError::MissingField { path, field, .. } => write!(f, "missing field {} on {}", field, path),
+            EvalError::UnknownFunction { name, .. } => write!(f, "unknown function {}", name),
+            EvalError::WrongArity { name, expected, found, .. } =>
+                write!(f, "{} takes {} argument(s) but {} were given", name, expected, found),
+            EvalError::FunctionFailed { name, message, .. } => write!(f, "{} failed: {}", name, message),
+            EvalError::TypeMismatch { expected, found, .. } =>
+                write!(f, "expected {}, found {}", expected, found),
+            EvalError::IndexOutOfBounds { index, len, .. } =>
+                write!(f, "index {} out of bounds for a list of length {}", index, len),
+            EvalError::DivisionByZero { .. } => write!(f, "division by zero"),
+            EvalError::Overflow { .. } => write!(f, "integer overflow"),
+        }?;
+        let start = self.span().start;
+        write!(f, " at {}:{}", start.line, start.column)
+    }
+}
+
+impl Error for EvalError {}
+
+type HostFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+struct Function {
+    arity: usize,
+    func: Box<HostFn>,
+}
+
+// How names written without the `$` sigil are resolved. Names with the
+// sigil always refer to host data, i.e. to variables stored with
+// `Context::set` and functions added with `Context::register`, so locals
+// can never shadow them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SigilPolicy {
+    // Bare names are looked up among locals and built-ins first, then
+    // among host data.
+    #[default]
+    Lenient,
+    // Bare names only refer to locals and built-ins.
+    Strict,
+}
+
+#[derive(Default)]
 pub struct Context {
+    variables: BTreeMap<String, Value>,
+    functions: HashMap<String, Function>,
+    locals: BTreeMap<String, Value>,
+    builtins: HashMap<String, Function>,
+    policy: SigilPolicy,
+}
+
+fn insert(map: &mut BTreeMap<String, Value>, path: &[&str], value: Value) {
+    let (field, rest) = path.split_first().unwrap();
+    if rest.is_empty() {
+        map.insert(field.to_string(), value);
+        return;
+    }
+    let entry = map.entry(field.to_string()).or_insert_with(|| Value::Map(BTreeMap::new()));
+    if let Value::Map(m) = entry {
+        return insert(m, rest, value);
+    }
+    let mut m = BTreeMap::new();
+    insert(&mut m, rest, value);
+    *entry = Value::Map(m);
 }
 
 impl Context {
+    pub fn new() -> Context {
+        Context {
+            variables: BTreeMap::new(),
+            functions: HashMap::new(),
+            locals: BTreeMap::new(),
+            builtins: HashMap::new(),
+            policy: SigilPolicy::default(),
+        }
+    }
+
+    pub fn sigil_policy(mut self, policy: SigilPolicy) -> Context {
+        self.policy = policy;
+        self
+    }
+
+    // Makes `func` callable from expressions as `name(arg0, ..)`, where
+    // `name` may be dotted. Calls with a number of arguments other than
+    // `arity` are rejected before `func` is invoked.
+    pub fn register<F>(&mut self, name: &str, arity: usize, func: F)
+        where F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        self.functions.insert(name.to_string(), Function { arity, func: Box::new(func) });
+    }
+
+    // Like `register`, but the function is only reachable without the sigil
+    pub fn register_builtin<F>(&mut self, name: &str, arity: usize, func: F)
+        where F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        self.builtins.insert(name.to_string(), Function { arity, func: Box::new(func) });
+    }
+
+    // A dotted `name` stores `value` as a field of nested maps, creating
+    // them as needed and replacing anything on the way that is not a map.
+    pub fn set<V: Into<Value>>(&mut self, name: &str, value: V) {
+        let path: Vec<&str> = name.split('.').collect();
+        insert(&mut self.variables, &path, value.into());
+    }
+
+    // Resolves a possibly dotted `name` by walking through nested maps
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let mut fields = name.split('.');
+        let mut curr = self.variables.get(fields.next().unwrap())?;
+        for field in fields {
+            curr = match curr {
+                Value::Map(m) => m.get(field)?,
+                _ => return None,
+            };
+        }
+        Some(curr)
+    }
+
+    // Like `set`, but the variable is only reachable without the sigil
+    pub fn set_local<V: Into<Value>>(&mut self, name: &str, value: V) {
+        let path: Vec<&str> = name.split('.').collect();
+        insert(&mut self.locals, &path, value.into());
+    }
+
+    fn variable(&self, name: &str, sigil: bool) -> Option<&Value> {
+        match (sigil, self.policy) {
+            (true, _) => self.variables.get(name),
+            (false, SigilPolicy::Strict) => self.locals.get(name),
+            (false, SigilPolicy::Lenient) => self.locals.get(name).or_else(|| self.variables.get(name)),
+        }
+    }
+
+    fn function(&self, name: &str, sigil: bool) -> Option<&Function> {
+        match (sigil, self.policy) {
+            (true, _) => self.functions.get(name),
+            (false, SigilPolicy::Strict) => self.builtins.get(name),
+            (false, SigilPolicy::Lenient) => self.builtins.get(name).or_else(|| self.functions.get(name)),
+        }
+    }
 }
 
 pub trait ASTNode {
-    fn interpret(ctx: &Context) {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError>;
+}
+
+// Operands of an arithmetic operation or of a comparison. An integer is
+// promoted to float when the other operand is a float.
+enum Numbers {
+    Integers(i64, i64),
+    Floats(f64, f64),
+}
+
+// Checks that `lhs` and `rhs`, produced by the respective nodes, are numbers
+fn numbers(lhs: Value, lhs_node: &Node, rhs: Value, rhs_node: &Node) -> Result<Numbers, EvalError> {
+    match (lhs, rhs) {
+        (Value::Integer(l), Value::Integer(r)) => Ok(Numbers::Integers(l, r)),
+        (Value::Integer(l), Value::Float(r)) => Ok(Numbers::Floats(l as f64, r)),
+        (Value::Float(l), Value::Integer(r)) => Ok(Numbers::Floats(l, r as f64)),
+        (Value::Float(l), Value::Float(r)) => Ok(Numbers::Floats(l, r)),
+        (Value::Integer(_), v) | (Value::Float(_), v) =>
+            Err(EvalError::TypeMismatch { expected: "number", found: v.type_name(), span: rhs_node.span() }),
+        (v, _) => Err(EvalError::TypeMismatch { expected: "number", found: v.type_name(), span: lhs_node.span() }),
+    }
+}
+
+fn evaluate_numbers(lhs: &Node, rhs: &Node, ctx: &Context) -> Result<Numbers, EvalError> {
+    numbers(lhs.interpret(ctx)?, lhs, rhs.interpret(ctx)?, rhs)
+}
+
+// Checks that `node` produces an integer
+fn integer(node: &Node, ctx: &Context) -> Result<i64, EvalError> {
+    match node.interpret(ctx)? {
+        Value::Integer(v) => Ok(v),
+        v => Err(EvalError::TypeMismatch { expected: "integer", found: v.type_name(), span: node.span() }),
+    }
+}
+
+// Infinite and NaN results are reported as an overflow
+fn float(value: f64, span: Span) -> Result<Value, EvalError> {
+    match value.is_finite() {
+        true => Ok(Value::Float(value)),
+        false => Err(EvalError::Overflow { span }),
     }
 }
 
+// Checks that `value`, produced by `node`, is a boolean
+fn expect_boolean(value: Value, node: &Node) -> Result<bool, EvalError> {
+    match value {
+        Value::Boolean(v) => Ok(v),
+        v => Err(EvalError::TypeMismatch { expected: "boolean", found: v.type_name(), span: node.span() }),
+    }
+}
+
+pub(crate) fn boolean(node: &Node, ctx: &Context) -> Result<bool, EvalError> {
+    expect_boolean(node.interpret(ctx)?, node)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Variable(VariableNode),
+    Integer(IntegerNode),
+    Float(FloatNode),
+    String(StringNode),
+    Boolean(BooleanNode),
+    Null(NullNode),
+    List(ListNode),
+    Map(MapNode),
+    Index(IndexNode),
+    Field(FieldNode),
+    FunctionCall(FunctionCallNode),
+    Unary(UnaryNode),
+    Term(TermNode),
+    Expr(ExprNode),
+    Bitwise(BitwiseNode),
+    Not(NotNode),
+    Condition(ConditionNode),
+    Membership(MembershipNode),
+    LogicTerm(LogicTermNode),
+    LogicExpr(LogicExprNode),
+    Ternary(TernaryNode),
+    Operator(OperatorNode),
+}
+
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Variable(n) => n.span,
+            Node::Integer(n) => n.span,
+            Node::Float(n) => n.span,
+            Node::String(n) => n.span,
+            Node::Boolean(n) => n.span,
+            Node::Null(n) => n.span,
+            Node::List(n) => n.span,
+            Node::Map(n) => n.span,
+            Node::Index(n) => n.span,
+            Node::Field(n) => n.span,
+            Node::FunctionCall(n) => n.span,
+            Node::Unary(n) => n.span,
+            Node::Term(n) => n.span,
+            Node::Expr(n) => n.span,
+            Node::Bitwise(n) => n.span,
+            Node::Not(n) => n.span,
+            Node::Condition(n) => n.span,
+            Node::Membership(n) => n.span,
+            Node::LogicTerm(n) => n.span,
+            Node::LogicExpr(n) => n.span,
+            Node::Ternary(n) => n.span,
+            Node::Operator(n) => n.span,
+        }
+    }
+}
+
+impl ASTNode for Node {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match self {
+            Node::Variable(n) => n.interpret(ctx),
+            Node::Integer(n) => n.interpret(ctx),
+            Node::Float(n) => n.interpret(ctx),
+            Node::String(n) => n.interpret(ctx),
+            Node::Boolean(n) => n.interpret(ctx),
+            Node::Null(n) => n.interpret(ctx),
+            Node::List(n) => n.interpret(ctx),
+            Node::Map(n) => n.interpret(ctx),
+            Node::Index(n) => n.interpret(ctx),
+            Node::Field(n) => n.interpret(ctx),
+            Node::FunctionCall(n) => n.interpret(ctx),
+            Node::Unary(n) => n.interpret(ctx),
+            Node::Term(n) => n.interpret(ctx),
+            Node::Expr(n) => n.interpret(ctx),
+            Node::Bitwise(n) => n.interpret(ctx),
+            Node::Not(n) => n.interpret(ctx),
+            Node::Condition(n) => n.interpret(ctx),
+            Node::Membership(n) => n.interpret(ctx),
+            Node::LogicTerm(n) => n.interpret(ctx),
+            Node::LogicExpr(n) => n.interpret(ctx),
+            Node::Ternary(n) => n.interpret(ctx),
+            Node::Operator(n) => n.interpret(ctx),
+        }
+    }
+}
+
+// Prints the expression fully parenthesized, e.g. `(1 + (2 * x))`
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Node::Variable(n) => write!(f, "{}{}", if n.sigil { "$" } else { "" }, n.path.join(".")),
+            Node::Integer(n) => write!(f, "{}", n.value),
+            Node::Float(n) => write!(f, "{:?}", n.value),
+            Node::String(n) => write!(f, "{:?}", n.value),
+            Node::Boolean(n) => write!(f, "{}", n.value),
+            Node::Null(_) => write!(f, "null"),
+            Node::List(n) => {
+                write!(f, "[")?;
+                for (i, item) in n.items.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Node::Map(n) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in n.entries.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{:?}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            },
+            Node::Index(n) => write!(f, "{}[{}]", n.target, n.index),
+            Node::Field(n) => write!(f, "{}.{}", n.target, n.field),
+            Node::FunctionCall(n) => {
+                write!(f, "{}{}(", if n.sigil { "$" } else { "" }, n.path.join("."))?;
+                for (i, arg) in n.args.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            },
+            Node::Unary(n) => write!(f, "{}{}", n.op, n.operand),
+            Node::Term(n) => write!(f, "({} {} {})", n.lhs, n.op, n.rhs),
+            Node::Expr(n) => write!(f, "({} {} {})", n.lhs, n.op, n.rhs),
+            Node::Bitwise(n) => write!(f, "({} {} {})", n.lhs, n.op, n.rhs),
+            Node::Not(n) => write!(f, "!{}", n.operand),
+            Node::Condition(n) => write!(f, "({} {} {})", n.lhs, n.op, n.rhs),
+            Node::Membership(n) => write!(f, "({} {} {})", n.lhs, if n.negated { "not in" } else { "in" }, n.rhs),
+            Node::LogicTerm(n) => write!(f, "({} && {})", n.lhs, n.rhs),
+            Node::LogicExpr(n) => write!(f, "({} || {})", n.lhs, n.rhs),
+            Node::Ternary(n) => write!(f, "({} ? {} : {})", n.condition, n.then_branch, n.else_branch),
+            Node::Operator(n) => {
+                // Word operators are kept apart from their operand
+                let space = if n.symbol.contains(char::is_alphanumeric) { " " } else { "" };
+                match n.fixity {
+                    Fixity::Prefix => write!(f, "{}{}{}", n.symbol, space, n.operands[0]),
+                    Fixity::Infix(_) => write!(f, "({} {} {})", n.operands[0], n.symbol, n.operands[1]),
+                    Fixity::Postfix => write!(f, "{}{}{}", n.operands[0], space, n.symbol),
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VariableNode {
+    // Whether the name was written with the leading `$`
+    pub sigil: bool,
+    pub path: Vec<String>,
+    pub span: Span,
+}
+
+impl ASTNode for VariableNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let root = &self.path[0];
+        let mut curr = match ctx.variable(root, self.sigil) {
+            Some(v) => v,
+            None => {
+                let name = format!("{}{}", if self.sigil { "$" } else { "" }, root);
+                return Err(EvalError::UnknownVariable { name, span: self.span });
+            },
+        };
+        for (i, field) in self.path.iter().enumerate().skip(1) {
+            curr = match curr {
+                Value::Map(m) => match m.get(field) {
+                    Some(v) => v,
+                    None => {
+                        let sigil = if self.sigil { "$" } else { "" };
+                        let (path, field) = (format!("{}{}", sigil, self.path[..i].join(".")), field.clone());
+                        return Err(EvalError::MissingField { path, field, span: self.span });
+                    },
+                },
+                v => return Err(EvalError::TypeMismatch { expected: "map", found: v.type_name(), span: self.span }),
+            };
+        }
+        Ok(curr.clone())
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct IntegerNode {
+    pub value: i64,
+    pub span: Span,
 }
 
+impl ASTNode for IntegerNode {
+    fn interpret(&self, _ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::Integer(self.value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatNode {
+    pub value: f64,
+    pub span: Span,
+}
+
+impl ASTNode for FloatNode {
+    fn interpret(&self, _ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::Float(self.value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringNode {
+    pub value: String,
+    pub span: Span,
+}
+
+impl ASTNode for StringNode {
+    fn interpret(&self, _ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::String(self.value.clone()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BooleanNode {
+    pub value: bool,
+    pub span: Span,
+}
+
+impl ASTNode for BooleanNode {
+    fn interpret(&self, _ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::Boolean(self.value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NullNode {
+    pub span: Span,
+}
+
+impl ASTNode for NullNode {
+    fn interpret(&self, _ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListNode {
+    pub items: Vec<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for ListNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let items = self.items.iter()
+            .map(|item| item.interpret(ctx))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Value::List(items))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapNode {
+    // In the order they were written, a repeated key overriding the
+    // previous ones
+    pub entries: Vec<(String, Node)>,
+    pub span: Span,
+}
+
+impl ASTNode for MapNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let mut map = BTreeMap::new();
+        for (key, value) in &self.entries {
+            map.insert(key.clone(), value.interpret(ctx)?);
+        }
+        Ok(Value::Map(map))
+    }
+}
+
+// A list element or a map entry, the latter resolving like a dotted name
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexNode {
+    pub target: Box<Node>,
+    pub index: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for IndexNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match (self.target.interpret(ctx)?, self.index.interpret(ctx)?) {
+            (Value::List(mut list), Value::Integer(index)) => match usize::try_from(index) {
+                Ok(i) if i < list.len() => Ok(list.swap_remove(i)),
+                // Negative indices are out of bounds as well
+                _ => Err(EvalError::IndexOutOfBounds { index, len: list.len(), span: self.index.span() }),
+            },
+            (Value::Map(mut map), Value::String(key)) => match map.remove(&key) {
+                Some(v) => Ok(v),
+                None => Err(EvalError::MissingField { path: self.target.to_string(), field: key, span: self.span }),
+            },
+            (Value::List(_), v) =>
+                Err(EvalError::TypeMismatch { expected: "integer", found: v.type_name(), span: self.index.span() }),
+            (Value::Map(_), v) =>
+                Err(EvalError::TypeMismatch { expected: "string", found: v.type_name(), span: self.index.span() }),
+            (v, Value::String(_)) =>
+                Err(EvalError::TypeMismatch { expected: "map", found: v.type_name(), span: self.target.span() }),
+            (v, _) => Err(EvalError::TypeMismatch { expected: "list", found: v.type_name(), span: self.target.span() }),
+        }
+    }
+}
+
+// A map entry read with a dot after any other value, as in
+// `$items[0].name`, resolving like the rest of a dotted name
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldNode {
+    pub target: Box<Node>,
+    pub field: String,
+    pub span: Span,
+}
+
+impl ASTNode for FieldNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match self.target.interpret(ctx)? {
+            Value::Map(mut map) => match map.remove(&self.field) {
+                Some(v) => Ok(v),
+                None => {
+                    let (path, field) = (self.target.to_string(), self.field.clone());
+                    Err(EvalError::MissingField { path, field, span: self.span })
+                },
+            },
+            v => Err(EvalError::TypeMismatch { expected: "map", found: v.type_name(), span: self.target.span() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCallNode {
+    // Whether the name was written with the leading `$`
+    pub sigil: bool,
+    pub path: Vec<String>,
+    pub args: Vec<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for FunctionCallNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let name = self.path.join(".");
+        let function = match ctx.function(&name, self.sigil) {
+            Some(function) => function,
+            None => {
+                let name = format!("{}{}", if self.sigil { "$" } else { "" }, name);
+                return Err(EvalError::UnknownFunction { name, span: self.span });
+            },
+        };
+        call(function, name, &self.args, self.span, ctx)
+    }
+}
+
+// Invokes `function` on the values of `args`, referring to it as `name` in errors
+fn call(function: &Function, name: String, args: &[Node], span: Span, ctx: &Context) -> Result<Value, EvalError> {
+    if function.arity != args.len() {
+        let (expected, found) = (function.arity, args.len());
+        return Err(EvalError::WrongArity { name, expected, found, span });
+    }
+    let args = args.iter()
+        .map(|arg| arg.interpret(ctx))
+        .collect::<Result<Vec<_>, _>>()?;
+    (function.func)(&args)
+        .map_err(|message| EvalError::FunctionFailed { name, message, span })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Minus,
+    Plus,
+    BitNot,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Minus => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::BitNot => "~",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnaryNode {
+    pub op: UnaryOp,
+    pub operand: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for UnaryNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match (self.op, self.operand.interpret(ctx)?) {
+            (UnaryOp::Minus, Value::Integer(v)) =>
+                v.checked_neg().map(Value::Integer).ok_or(EvalError::Overflow { span: self.span }),
+            (UnaryOp::Minus, Value::Float(v)) => Ok(Value::Float(-v)),
+            (UnaryOp::Plus, v @ Value::Integer(_)) | (UnaryOp::Plus, v @ Value::Float(_)) => Ok(v),
+            (UnaryOp::BitNot, Value::Integer(v)) => Ok(Value::Integer(!v)),
+            (UnaryOp::BitNot, v) =>
+                Err(EvalError::TypeMismatch { expected: "integer", found: v.type_name(), span: self.operand.span() }),
+            (_, v) => Err(EvalError::TypeMismatch { expected: "number", found: v.type_name(), span: self.operand.span() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitwiseOp {
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl fmt::Display for BitwiseOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BitwiseOp::And => "&",
+            BitwiseOp::Or => "|",
+            BitwiseOp::Xor => "^",
+            BitwiseOp::Shl => "<<",
+            BitwiseOp::Shr => ">>",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitwiseNode {
+    pub op: BitwiseOp,
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for BitwiseNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let (lhs, rhs) = (integer(&self.lhs, ctx)?, integer(&self.rhs, ctx)?);
+        // Shifting by a negative amount or by 64 bits or more overflows, and
+        // so does `<<` shifting out bits that `>>` can not bring back, as
+        // `*` would. `>>` keeps the sign.
+        let shift = u32::try_from(rhs).ok();
+        let ret = match self.op {
+            BitwiseOp::And => Some(lhs & rhs),
+            BitwiseOp::Or => Some(lhs | rhs),
+            BitwiseOp::Xor => Some(lhs ^ rhs),
+            BitwiseOp::Shl => shift
+                .and_then(|s| lhs.checked_shl(s))
+                .filter(|v| v >> rhs == lhs),
+            BitwiseOp::Shr => shift.and_then(|s| lhs.checked_shr(s)),
+        };
+        ret.map(Value::Integer).ok_or(EvalError::Overflow { span: self.span })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TermOp {
+    Mul,
+    Div,
+    Mod,
+}
+
+impl fmt::Display for TermOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TermOp::Mul => "*",
+            TermOp::Div => "/",
+            TermOp::Mod => "%",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TermNode {
+    pub op: TermOp,
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for TermNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match evaluate_numbers(&self.lhs, &self.rhs, ctx)? {
+            Numbers::Integers(lhs, rhs) => {
+                if rhs == 0 && self.op != TermOp::Mul {
+                    return Err(EvalError::DivisionByZero { span: self.span });
+                }
+                let ret = match self.op {
+                    TermOp::Mul => lhs.checked_mul(rhs),
+                    TermOp::Div => lhs.checked_div(rhs),
+                    TermOp::Mod => lhs.checked_rem(rhs),
+                };
+                ret.map(Value::Integer).ok_or(EvalError::Overflow { span: self.span })
+            },
+            Numbers::Floats(lhs, rhs) => {
+                if rhs == 0.0 && self.op != TermOp::Mul {
+                    return Err(EvalError::DivisionByZero { span: self.span });
+                }
+                float(match self.op {
+                    TermOp::Mul => lhs * rhs,
+                    TermOp::Div => lhs / rhs,
+                    TermOp::Mod => lhs % rhs,
+                }, self.span)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExprOp {
+    Add,
+    Sub,
 }
 
+impl fmt::Display for ExprOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExprOp::Add => "+",
+            ExprOp::Sub => "-",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ExprNode {
+    pub op: ExprOp,
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for ExprNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match evaluate_numbers(&self.lhs, &self.rhs, ctx)? {
+            Numbers::Integers(lhs, rhs) => {
+                let ret = match self.op {
+                    ExprOp::Add => lhs.checked_add(rhs),
+                    ExprOp::Sub => lhs.checked_sub(rhs),
+                };
+                ret.map(Value::Integer).ok_or(EvalError::Overflow { span: self.span })
+            },
+            Numbers::Floats(lhs, rhs) => float(match self.op {
+                ExprOp::Add => lhs + rhs,
+                ExprOp::Sub => lhs - rhs,
+            }, self.span),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotNode {
+    pub operand: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for NotNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        Ok(Value::Boolean(!boolean(&self.operand, ctx)?))
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl fmt::Display for ComparisonOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ComparisonOp::Eq => "==",
+            ComparisonOp::Ne => "!=",
+            ComparisonOp::Lt => "<",
+            ComparisonOp::Le => "<=",
+            ComparisonOp::Gt => ">",
+            ComparisonOp::Ge => ">=",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConditionNode {
+    pub op: ComparisonOp,
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for ConditionNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let lhs = self.lhs.interpret(ctx)?;
+        let rhs = self.rhs.interpret(ctx)?;
+        let is_number = |v: &Value| matches!(v, Value::Integer(_) | Value::Float(_));
+        let equality = self.op == ComparisonOp::Eq || self.op == ComparisonOp::Ne;
+        if equality && !(is_number(&lhs) && is_number(&rhs)) {
+            // Equality is defined between any two values of the same type,
+            // and between null and anything else
+            let null = lhs == Value::Null || rhs == Value::Null;
+            if !null && lhs.type_name() != rhs.type_name() {
+                let (expected, found) = (lhs.type_name(), rhs.type_name());
+                return Err(EvalError::TypeMismatch { expected, found, span: self.rhs.span() });
+            }
+            return Ok(Value::Boolean(equal(&lhs, &rhs) == (self.op == ComparisonOp::Eq)));
+        }
+        if let (Value::String(lhs), Value::String(rhs)) = (&lhs, &rhs) {
+            // Strings are ordered lexicographically by code point
+            return Ok(Value::Boolean(compare(self.op, lhs, rhs)));
+        }
+        Ok(Value::Boolean(match numbers(lhs, &self.lhs, rhs, &self.rhs)? {
+            Numbers::Integers(lhs, rhs) => compare(self.op, lhs, rhs),
+            Numbers::Floats(lhs, rhs) => compare(self.op, lhs, rhs),
+        }))
+    }
+}
+
+fn compare<T: PartialOrd>(op: ComparisonOp, lhs: T, rhs: T) -> bool {
+    match op {
+        ComparisonOp::Eq => lhs == rhs,
+        ComparisonOp::Ne => lhs != rhs,
+        ComparisonOp::Lt => lhs < rhs,
+        ComparisonOp::Le => lhs <= rhs,
+        ComparisonOp::Gt => lhs > rhs,
+        ComparisonOp::Ge => lhs >= rhs,
+    }
+}
+
+// Equality as seen by `==`, except that values of different types are
+// simply not equal. Numbers compare by value, in lists and maps too.
+fn equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Integer(l), Value::Float(r)) => *l as f64 == *r,
+        (Value::Float(l), Value::Integer(r)) => *l == *r as f64,
+        (Value::List(l), Value::List(r)) => {
+            l.len() == r.len() && l.iter().zip(r).all(|(l, r)| equal(l, r))
+        },
+        (Value::Map(l), Value::Map(r)) => {
+            l.len() == r.len() && l.iter().all(|(key, l)| r.get(key).is_some_and(|r| equal(l, r)))
+        },
+        _ => lhs == rhs,
+    }
 }
 
+// `lhs in rhs`, or `lhs not in rhs` when negated
+#[derive(Debug, Clone, PartialEq)]
+pub struct MembershipNode {
+    pub negated: bool,
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for MembershipNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        let lhs = self.lhs.interpret(ctx)?;
+        let found = match self.rhs.interpret(ctx)? {
+            Value::List(l) => l.iter().any(|v| equal(&lhs, v)),
+            Value::Map(m) => match &lhs {
+                Value::String(key) => m.contains_key(key),
+                v => return Err(EvalError::TypeMismatch { expected: "string", found: v.type_name(), span: self.lhs.span() }),
+            },
+            Value::String(s) => match &lhs {
+                Value::String(sub) => s.contains(sub.as_str()),
+                v => return Err(EvalError::TypeMismatch { expected: "string", found: v.type_name(), span: self.lhs.span() }),
+            },
+            v => return Err(EvalError::TypeMismatch { expected: "list, map or string", found: v.type_name(), span: self.rhs.span() }),
+        };
+        Ok(Value::Boolean(found != self.negated))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LogicTermNode {
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for LogicTermNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        // The right hand side is only evaluated when needed
+        Ok(Value::Boolean(boolean(&self.lhs, ctx)? && boolean(&self.rhs, ctx)?))
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct LogicExprNode {
+    pub lhs: Box<Node>,
+    pub rhs: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for LogicExprNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        // The right hand side is only evaluated when needed
+        Ok(Value::Boolean(boolean(&self.lhs, ctx)? || boolean(&self.rhs, ctx)?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TernaryNode {
+    pub condition: Box<Node>,
+    pub then_branch: Box<Node>,
+    pub else_branch: Box<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for TernaryNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        // Only the chosen branch is evaluated
+        match boolean(&self.condition, ctx)? {
+            true => self.then_branch.interpret(ctx),
+            false => self.else_branch.interpret(ctx),
+        }
+    }
+}
+
+// Operator added to the parser by the embedder, evaluated by calling the
+// host function `function` on the operands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorNode {
+    pub symbol: String,
+    pub fixity: Fixity,
+    pub function: String,
+    pub operands: Vec<Node>,
+    pub span: Span,
+}
+
+impl ASTNode for OperatorNode {
+    fn interpret(&self, ctx: &Context) -> Result<Value, EvalError> {
+        match ctx.function(&self.function, true) {
+            Some(function) => call(function, self.function.clone(), &self.operands, self.span, ctx),
+            None => Err(EvalError::UnknownFunction { name: self.function.clone(), span: self.span }),
+        }
+    }
 }
@@ -18,10 +18,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-#[macro_use] mod basic_parser;
+pub mod basic_parser;
 pub mod parser;
 pub mod interpreter;
 
-#[cfg(test)] mod ts_state;
-#[cfg(test)] mod ts_parser_macros;
+// The upstream tests are kept as they were written
+#[cfg(test)] #[allow(clippy::bool_assert_comparison)] mod ts_state;
+#[cfg(test)] #[allow(unused_mut)] mod ts_basic_parser;
+#[cfg(test)] mod ts_combinators;
+#[cfg(test)] mod ts_interpreter;
 
@@ -18,261 +18,804 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::basic_parser::State;
-use crate::basic_parser::Transition;
+use std::error;
+use std::fmt;
 
-// name     := letter [alphanum_str]
+use crate::basic_parser::Parser as _;
+use crate::basic_parser::{
+    alphanum_str, chr, chr_range, digit, letter, unicode_alphanum_str, unicode_letter, Failure, Parsed,
+    State,
+};
+pub use crate::basic_parser::{ParseError, Position, Span};
+use crate::interpreter;
+use crate::interpreter::{
+    BitwiseNode, BitwiseOp, BooleanNode, ComparisonOp, ConditionNode, Context, EvalError, ExprNode,
+    ExprOp, FieldNode, FloatNode, FunctionCallNode, IndexNode, IntegerNode, ListNode, LogicExprNode,
+    LogicTermNode, MapNode, MembershipNode, Node, NotNode, NullNode, OperatorNode, StringNode,
+    TermNode, TermOp, TernaryNode, UnaryNode, UnaryOp, VariableNode,
+};
+
+// Words that can not be used as names
+const RESERVED: [&str; 3] = ["true", "false", "null"];
+
+// name     := letter [alphanum_str]    (XID_Start XID_Continue* with unicode identifiers),
+//             except for the reserved words of `literal`
 // dotname  := (name '.')* name
 // var      := ['$'] dotname
-// args     := (id ',')* id
+// args     := [(expr ',')* expr]
 // fcall    := ['$'] dotname '(' args ')'
-// value    := number
-// number   := natural
-// natural  := '0' | ('1' | '2' | ... | '9') digit*
-// id       := const | fcall | var
-// factor   := '(' expr ')' | id
-// term     := factor (('*' | '/' | '%') factor)*
-// expr     := term (('+' | '-') term)*
+// value    := number | string | literal
+// literal  := 'true' | 'false' | 'null'
+// string   := '"' (char | escape)* '"' | "'" (char | escape)* "'"
+// escape   := '\\' ('n' | 't' | 'r' | '0' | '\\' | '"' | "'" | 'u{' hex+ '}')
+// number   := float | natural
+// float    := (decimal '.' digits | '.' digits) [exponent] | decimal exponent
+// exponent := ('e' | 'E') ['+' | '-'] digits
+// natural  := ('0x' | '0X') hex_digits | ('0o' | '0O') oct_digits | ('0b' | '0B') bin_digits | decimal
+// decimal  := '0' | ('1' | '2' | ... | '9') ['_'] [digits]
+// digits   := digit (['_'] digit)*    (likewise for hexadecimal, octal and binary digits)
+// list     := '[' args ']'
+// entry    := string ':' expr
+// map      := '{' [(entry ',')* entry] '}'
+// id       := fcall | value | list | map | var
+// index    := '[' expr ']'
+// field    := '.' name
+// primary  := ('(' expr ')' | id) (index | field)*
+// operand  := prefix_op operand | primary
+// expr     := operand (infix_op expr | postfix_op | '?' expr ':' expr)*
 //
-// cond         := '!' logic_factor | (expr ('==' | '!=' | '<' | '<=' | '>' | '>=') expr)
-// logic_factor := '(' logic_expr ')' | cond
-// logic_term   := logic_factor ('||' logic_factor)*
-// logix_expr   := logic_term ('&&' logic_term)*
+// Operators come from the parser's operator table and bind according to
+// their precedence and associativity, the ternary conditional having the
+// lowest precedence of all.
+
+// Every rule reports the node built from the matched text together with the
+// state right after the match.
+
+/// Why `Parser::parse` could not tell whether an expression holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The input is not a valid expression
+    Parse(ParseError),
+    /// The expression failed to evaluate against the context
+    Eval(EvalError),
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(err: EvalError) -> Error {
+        Error::Eval(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => err.fmt(f),
+            Error::Eval(err) => err.fmt(f),
+        }
+    }
+}
 
+impl error::Error for Error {}
+
+/// Precedence of the built-in operators, from the loosest to the tightest.
+/// Operators added with `Parser::operator` are placed relative to these.
+pub mod precedence {
+    pub const TERNARY: u32 = 10;
+    pub const OR: u32 = 20;
+    pub const AND: u32 = 30;
+    /// Lower than comparisons, so that `!x > 1` is `!(x > 1)`
+    pub const NOT: u32 = 40;
+    pub const COMPARISON: u32 = 50;
+    /// Bitwise operators are ordered as in C, but unlike in C they bind
+    /// tighter than comparisons, so that `$flags & 4 != 0` tests a bit.
+    pub const BIT_OR: u32 = 60;
+    pub const BIT_XOR: u32 = 70;
+    pub const BIT_AND: u32 = 80;
+    pub const SHIFT: u32 = 90;
+    pub const SUM: u32 = 100;
+    pub const PRODUCT: u32 = 110;
+    pub const UNARY: u32 = 120;
+}
+
+/// How a chain of infix operators of the same precedence is grouped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ** b ** c` is `a ** (b ** c)`, as for a power operator
+    Right,
+}
+
+/// Where an operator stands relative to its operands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fixity {
+    /// Before its operand, as in `-x`
+    Prefix,
+    /// Between its operands, as in `a + b`
+    Infix(Associativity),
+    /// After its operand, as in `n!`
+    Postfix,
+}
+
+// Node built for an operator
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Unary(UnaryOp),
+    Not,
+    Term(TermOp),
+    Expr(ExprOp),
+    Bitwise(BitwiseOp),
+    Comparison(ComparisonOp),
+    // Negated for `not in`
+    Membership(bool),
+    And,
+    Or,
+    // Call of the host function with the given name
+    Call(String),
+}
+
+/// An entry of the operator table, added with `Parser::operator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    symbol: String,
+    fixity: Fixity,
+    precedence: u32,
+    action: Action,
+}
+
+impl Operator {
+    /// The operand of `symbol` is passed to the host function registered
+    /// as `function` with `Context::register`.
+    ///
+    /// A space in `symbol` matches any run of whitespace, as in `not in`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symbol` is empty, starts or ends with whitespace, or has
+    /// whitespace other than single spaces inside, as it could never match.
+    pub fn prefix(symbol: &str, precedence: u32, function: &str) -> Operator {
+        Operator::new(symbol, Fixity::Prefix, precedence, Action::Call(function.to_string()))
+    }
+
+    /// Like `prefix`, with the left and right operands passed in this order
+    pub fn infix(symbol: &str, precedence: u32, associativity: Associativity, function: &str) -> Operator {
+        let fixity = Fixity::Infix(associativity);
+        Operator::new(symbol, fixity, precedence, Action::Call(function.to_string()))
+    }
+
+    /// Like `prefix`, for an operator following its operand
+    pub fn postfix(symbol: &str, precedence: u32, function: &str) -> Operator {
+        Operator::new(symbol, Fixity::Postfix, precedence, Action::Call(function.to_string()))
+    }
+
+    fn new(symbol: &str, fixity: Fixity, precedence: u32, action: Action) -> Operator {
+        // `symbol()` matches a space with a whole run of whitespace, so
+        // anything more than a single space in between never matches
+        let inner_ws = symbol.chars().zip(symbol.chars().skip(1))
+            .any(|(a, b)| (a.is_whitespace() && a != ' ') || (a == ' ' && b.is_whitespace()));
+        let valid = !symbol.is_empty()
+            && !symbol.starts_with(char::is_whitespace)
+            && !symbol.ends_with(char::is_whitespace)
+            && !inner_ws;
+        assert!(valid, "invalid operator symbol {:?}", symbol);
+        Operator { symbol: symbol.to_string(), fixity, precedence, action }
+    }
+
+    // Node for a prefix or postfix use of the operator
+    fn unary_node(&self, operand: Node, span: Span) -> Node {
+        match &self.action {
+            Action::Unary(op) => Node::Unary(UnaryNode { op: *op, operand: Box::new(operand), span }),
+            Action::Not => Node::Not(NotNode { operand: Box::new(operand), span }),
+            Action::Call(function) => self.call_node(function, vec![operand], span),
+            _ => unreachable!("{} used as a unary operator", self.symbol),
+        }
+    }
+
+    fn infix_node(&self, lhs: Node, rhs: Node, span: Span) -> Node {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        match &self.action {
+            Action::Term(op) => Node::Term(TermNode { op: *op, lhs, rhs, span }),
+            Action::Expr(op) => Node::Expr(ExprNode { op: *op, lhs, rhs, span }),
+            Action::Bitwise(op) => Node::Bitwise(BitwiseNode { op: *op, lhs, rhs, span }),
+            Action::Comparison(op) => Node::Condition(ConditionNode { op: *op, lhs, rhs, span }),
+            Action::Membership(negated) => Node::Membership(MembershipNode { negated: *negated, lhs, rhs, span }),
+            Action::And => Node::LogicTerm(LogicTermNode { lhs, rhs, span }),
+            Action::Or => Node::LogicExpr(LogicExprNode { lhs, rhs, span }),
+            Action::Call(function) => self.call_node(function, vec![*lhs, *rhs], span),
+            _ => unreachable!("{} used as an infix operator", self.symbol),
+        }
+    }
+
+    fn call_node(&self, function: &str, operands: Vec<Node>, span: Span) -> Node {
+        let (symbol, function) = (self.symbol.clone(), function.to_string());
+        Node::Operator(OperatorNode { symbol, fixity: self.fixity, function, operands, span })
+    }
+}
+
+fn builtin_operators() -> Vec<Operator> {
+    let left = Fixity::Infix(Associativity::Left);
+    let unary = |symbol, op| Operator::new(symbol, Fixity::Prefix, precedence::UNARY, Action::Unary(op));
+    let comparison = |symbol, op| Operator::new(symbol, left, precedence::COMPARISON, Action::Comparison(op));
+    let sum = |symbol, op| Operator::new(symbol, left, precedence::SUM, Action::Expr(op));
+    let product = |symbol, op| Operator::new(symbol, left, precedence::PRODUCT, Action::Term(op));
+    let bitwise = |symbol, precedence, op| Operator::new(symbol, left, precedence, Action::Bitwise(op));
+    // `&&` and `||` are told apart from `&` and `|` by taking the longest
+    // operator that matches.
+    vec![
+        Operator::new("||", left, precedence::OR, Action::Or),
+        Operator::new("&&", left, precedence::AND, Action::And),
+        Operator::new("!", Fixity::Prefix, precedence::NOT, Action::Not),
+        comparison("==", ComparisonOp::Eq),
+        comparison("!=", ComparisonOp::Ne),
+        comparison("<", ComparisonOp::Lt),
+        comparison("<=", ComparisonOp::Le),
+        comparison(">", ComparisonOp::Gt),
+        comparison(">=", ComparisonOp::Ge),
+        Operator::new("in", left, precedence::COMPARISON, Action::Membership(false)),
+        Operator::new("not in", left, precedence::COMPARISON, Action::Membership(true)),
+        sum("+", ExprOp::Add),
+        sum("-", ExprOp::Sub),
+        product("*", TermOp::Mul),
+        product("/", TermOp::Div),
+        product("%", TermOp::Mod),
+        unary("-", UnaryOp::Minus),
+        unary("+", UnaryOp::Plus),
+        unary("~", UnaryOp::BitNot),
+        bitwise("|", precedence::BIT_OR, BitwiseOp::Or),
+        bitwise("^", precedence::BIT_XOR, BitwiseOp::Xor),
+        bitwise("&", precedence::BIT_AND, BitwiseOp::And),
+        bitwise("<<", precedence::SHIFT, BitwiseOp::Shl),
+        bitwise(">>", precedence::SHIFT, BitwiseOp::Shr),
+    ]
+}
+
+/// Parses and evaluates logic expressions such as `$a.b + 2 > 3 && !(x == 1)`
 pub struct Parser {
+    unicode_identifiers: bool,
+    operators: Vec<Operator>,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
 }
 
 impl Parser {
+    /// A parser with ASCII names and the built-in operators
     pub fn new() -> Parser {
-        Parser {}
+        Parser {
+            unicode_identifiers: false,
+            operators: builtin_operators(),
+        }
     }
 
-    pub fn parse(&self, s: &str) -> Option<bool> {
-        None
+    /// When enabled, names follow the Unicode XID_Start/XID_Continue rules
+    /// instead of being restricted to ASCII letters, digits and '_'.
+    pub fn unicode_identifiers(mut self, enabled: bool) -> Parser {
+        self.unicode_identifiers = enabled;
+        self
     }
 
-    fn ws(&self, state: &State) -> State {
-        any!(alt!(chr!(' '), chr!('\t'), chr!('\n'), chr!('\r')))
-            .matches(state)
-            .unwrap()
+    /// Adds `operator` to the operator table. It replaces the operator with
+    /// the same symbol, if any, unless one of the two is a prefix operator
+    /// and the other is not.
+    pub fn operator(mut self, operator: Operator) -> Parser {
+        let prefix = operator.fixity == Fixity::Prefix;
+        self.operators.retain(|op| op.symbol != operator.symbol || (op.fixity == Fixity::Prefix) != prefix);
+        self.operators.push(operator);
+        self
     }
 
-    fn natural(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let zero = chr!('0').matches(&trimmed);
-        if zero.is_some() {
-            let next_char = zero.as_ref().unwrap().peek();
-            if next_char.is_digit(10) { return None; }
-            return zero;
-        }
-        seq!(chr!('1', '9'), rep!(digit!(), '*'))
-            .matches(&trimmed)
+    /// Parses `s` and evaluates it against `ctx`, which must yield a boolean
+    pub fn parse(&self, s: &str, ctx: &Context) -> Result<bool, Error> {
+        let node = self.parse_ast(s)?;
+        Ok(interpreter::boolean(&node, ctx)?)
     }
 
-    fn number(&self, state: &State) -> Option<State> {
-        self.natural(&state)
+    /// Parses the whole of `s` into a tree, to be evaluated later
+    pub fn parse_ast(&self, s: &str) -> Result<Node, ParseError> {
+        // Furthest position at which the grammar failed, along with
+        // everything that would have been accepted there
+        let mut failure = Failure::new();
+        let start = State::from_string(s);
+        if let Some((node, state)) = self.expr(&start, &mut failure) {
+            let end = self.ws(&state);
+            if end.complete() { return Ok(node); }
+            failure.fail(&end, "end of input");
+        }
+        Err(failure.into_error().unwrap())
     }
 
-    fn value(&self, state: &State) -> Option<State> {
-        self.number(&state)
+    // Where whitespace stops is never reported, as it is never what is
+    // missing
+    fn ws<'a>(&self, state: &State<'a>) -> State<'a> {
+        chr(' ').or(chr('\t')).or(chr('\n')).or(chr('\r')).many0()
+            .matches(state)
+            .unwrap()
     }
 
-    fn name(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        match letter!().matches(&trimmed) {
-            Some(s) => opt!(alphanum_str!()).matches(&s),
-            None => None,
+    // Recognizes digits in `radix`, with single underscores allowed in
+    // between
+    fn digits<'a>(&self, state: &State<'a>, radix: u32, label: &str, failure: &mut Failure) -> Option<State<'a>> {
+        if !state.peek().is_digit(radix) {
+            failure.fail(state, label);
+            return None;
+        }
+        let mut curr = state.read(1);
+        loop {
+            let next = match curr.peek() {
+                '_' => curr.read(1),
+                _ => curr,
+            };
+            if !next.peek().is_digit(radix) {
+                if next.offset() != curr.offset() { failure.fail(&next, label); }
+                return Some(curr);
+            }
+            curr = next.read(1);
         }
     }
 
-    fn dotted_name(&self, state: &State) -> Option<State> {
-        let mut curr = state.clone();
-        loop {
-            let name = self.name(&curr);
-            if name.is_none() { return None; }
-            let dot = chr!('.').matches(&name.as_ref().unwrap());
-            if dot.is_none() { return name; }
-            curr = dot.unwrap();
+    // Recognizes a natural along with its radix. Decimals can not have
+    // leading zeros.
+    fn natural_digits<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Option<(u32, State<'a>)> {
+        let (radix, label) = match state.peek_many(2) {
+            "0x" | "0X" => (16, "hexadecimal digit"),
+            "0o" | "0O" => (8, "octal digit"),
+            "0b" | "0B" => (2, "binary digit"),
+            _ => (10, "digit"),
+        };
+        if radix != 10 {
+            return self.digits(&state.read(2), radix, label, failure).map(|end| (radix, end));
+        }
+        let first = digit().label("number").matches_tracked(state, failure)?;
+        if state.peek() != '0' {
+            return self.digits(state, 10, label, failure).map(|end| (10, end));
         }
+        if first.peek().is_ascii_digit() || first.peek() == '_' {
+            failure.fail(&first, "end of number");
+            return None;
+        }
+        Some((10, first))
     }
 
-    fn var(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let dollar = opt!(chr!('$')).matches(&trimmed);
-        self.dotted_name(&dollar.unwrap())
+    fn natural<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let (radix, end) = self.natural_digits(&trimmed, failure)?;
+        let text = trimmed.slice_to(&end).replace('_', "");
+        let digits = if radix == 10 { &text[..] } else { &text[2..] };
+        let value = match i64::from_str_radix(digits, radix) {
+            Ok(value) => value,
+            Err(_) => {
+                failure.reject(&trimmed, &end, "integer literal out of range");
+                return None;
+            },
+        };
+        Some((Node::Integer(IntegerNode { value, span: Span::new(&trimmed, &end) }), end))
     }
 
-    fn args(&self, state: &State) -> Option<State> {
-        let mut i = 0;
-        let mut curr = state.clone();
+    fn number<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let int = self.natural_digits(&trimmed, failure);
+        // Only decimals can have a fraction or an exponent, as `e` is a
+        // hexadecimal digit
+        let start = match int {
+            Some((10, end)) => end,
+            Some(_) => return self.natural(&trimmed, failure),
+            None => trimmed,
+        };
+        let fraction = chr('.').hidden().matches_tracked(&start, failure)
+            .filter(|dot| dot.peek().is_ascii_digit())
+            .and_then(|dot| self.digits(&dot, 10, "digit", failure));
+        if int.is_none() && fraction.is_none() { return None; }
+        let exponent = chr('e').or(chr('E')).then(chr('+').or(chr('-')).opt()).hidden()
+            .matches_tracked(&fraction.unwrap_or(start), failure)
+            .filter(|sign| sign.peek().is_ascii_digit())
+            .and_then(|sign| self.digits(&sign, 10, "digit", failure));
+        if fraction.is_none() && exponent.is_none() {
+            return self.natural(&trimmed, failure);
+        }
+        let end = exponent.or(fraction).unwrap();
+        let value: f64 = trimmed.slice_to(&end).replace('_', "").parse().unwrap();
+        if !value.is_finite() {
+            failure.reject(&trimmed, &end, "number literal out of range");
+            return None;
+        }
+        Some((Node::Float(FloatNode { value, span: Span::new(&trimmed, &end) }), end))
+    }
+
+    fn string<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let (quote, label) = match trimmed.peek() {
+            '"' => ('"', "'\"'"),
+            '\'' => ('\'', "\"'\""),
+            _ => {
+                failure.fail(&trimmed, "string");
+                return None;
+            },
+        };
+        let mut value = String::new();
+        let mut curr = trimmed.read(1);
         loop {
-            let arg = self.id(&curr);
-            if arg.is_none() {
-                return match i {
-                    0 => Some(curr),
-                    _ => None,
-                }
+            if curr.complete() {
+                failure.fail(&curr, label);
+                return None;
             }
-            let trimmed = self.ws(&arg.as_ref().unwrap());
-            let comma = chr!(',').matches(&trimmed);
-            if comma.is_none() {
-                return arg;
+            match curr.peek() {
+                c if c == quote => break,
+                '\\' => {
+                    let (c, end) = self.escape(&curr, failure)?;
+                    value.push(c);
+                    curr = end;
+                },
+                c => {
+                    value.push(c);
+                    curr = curr.read(1);
+                },
             }
-            curr = comma.unwrap();
-            i += 1;
         }
+        let end = curr.read(1);
+        Some((Node::String(StringNode { value, span: Span::new(&trimmed, &end) }), end))
     }
 
-    fn fcall(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let dollar = opt!(chr!('$')).matches(&trimmed);
-        let fn_name = self.dotted_name(&dollar.unwrap());
-        if fn_name.is_none() { return None; }
-        let lbrace = chr!('(').matches(&fn_name.unwrap());
-        if lbrace.is_none() { return None; }
-        let args = self.args(&lbrace.unwrap());
-        if args.is_none() { return None; }
-        chr!(')').matches(&args.unwrap())
-    }
-
-    fn id(&self, state:&State) -> Option<State> {
-        let mut ret = self.fcall(&state);
-        if ret.is_some() { return ret; }
-        ret = self.value(&state);
-        if ret.is_some() { return ret; }
-        self.var(&state)
-    }
-
-    fn factor(&self, state: &State) -> Option<State> {
-        let mut trimmed = self.ws(&state);
-        let id = self.id(&trimmed);
-        if id.is_some() { return id; }
-        let lbrace = chr!('(').matches(&trimmed);
-        if lbrace.is_none() {return None;}
-        let expr = self.expr(&lbrace.unwrap());
-        if expr.is_none() { return None; }
-        trimmed = self.ws(&expr.unwrap());
-        chr!(')').matches(&trimmed)
-    }
-
-    fn term_rhs(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let op = alt!(chr!('*'), chr!('/'), chr!('%')).matches(&trimmed);
-        if op.is_none() { return None; }
-        self.factor(&op.unwrap())
-    }
-
-    fn term(&self, state: &State) -> Option<State> {
-        let mut ret = self.factor(&state);
-        if ret.is_none() {
+    fn escape<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, char> {
+        let escaped = chr('\\').matches_tracked(state, failure)?;
+        let c = match escaped.peek() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            c @ '\\' | c @ '"' | c @ '\'' => c,
+            'u' => {
+                let lbrace = chr('{').matches_tracked(&escaped.read(1), failure)?;
+                let hex = digit().or(chr_range('a', 'f')).or(chr_range('A', 'F')).many1();
+                let digits = hex.label("hexadecimal digit").matches_tracked(&lbrace, failure)?;
+                let rbrace = chr('}').matches_tracked(&digits, failure)?;
+                let code = u32::from_str_radix(lbrace.slice_to(&digits), 16).ok();
+                return match code.and_then(std::char::from_u32) {
+                    Some(c) => Some((c, rbrace)),
+                    None => {
+                        failure.reject(&lbrace, &rbrace, "invalid unicode escape");
+                        None
+                    },
+                };
+            },
+            _ => {
+                failure.fail(&escaped, "escape sequence");
+                return None;
+            },
+        };
+        Some((c, escaped.read(1)))
+    }
+
+    fn literal<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let (word, end) = self.identifier(&trimmed, failure)?;
+        let span = Span::new(&trimmed, &end);
+        let node = match word.as_str() {
+            "true" => Node::Boolean(BooleanNode { value: true, span }),
+            "false" => Node::Boolean(BooleanNode { value: false, span }),
+            "null" => Node::Null(NullNode { span }),
+            _ => return None,
+        };
+        Some((node, end))
+    }
+
+    fn value<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        self.number(state, failure)
+            .or_else(|| self.string(state, failure))
+            .or_else(|| self.literal(state, failure))
+    }
+
+    fn name<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, String> {
+        let trimmed = self.ws(state);
+        let (name, end) = self.identifier(&trimmed, failure)?;
+        if RESERVED.contains(&name.as_str()) {
+            failure.fail(&trimmed, "name");
             return None;
         }
+        Some((name, end))
+    }
+
+    // Like `name`, reserved words included
+    fn identifier<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, String> {
+        let trimmed = self.ws(state);
+        let end = match self.unicode_identifiers {
+            true => {
+                let s = unicode_letter().label("name").matches_tracked(&trimmed, failure)?;
+                unicode_alphanum_str().opt().hidden().matches_tracked(&s, failure)?
+            },
+            false => {
+                let s = letter().label("name").matches_tracked(&trimmed, failure)?;
+                alphanum_str().opt().hidden().matches_tracked(&s, failure)?
+            },
+        };
+        Some((trimmed.slice_to(&end).to_string(), end))
+    }
+
+    fn dotted_name<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Vec<String>> {
+        let mut path = Vec::new();
+        let mut curr = *state;
         loop {
-            let curr = self.term_rhs(&ret.as_ref().unwrap());
-            if curr.is_none() { return ret; }
-            ret = curr;
+            let (name, end) = self.name(&curr, failure)?;
+            path.push(name);
+            match chr('.').hidden().matches_tracked(&end, failure) {
+                Some(dot) => curr = dot,
+                None => return Some((path, end)),
+            }
         }
     }
 
-    fn expr_rhs(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let op = alt!(chr!('+'), chr!('-')).matches(&trimmed);
-        if op.is_none() { return None; }
-        self.term(&op.unwrap())
+    fn var<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let dollar = chr('$').hidden().matches_tracked(&trimmed, failure);
+        let (path, end) = self.dotted_name(&dollar.unwrap_or(trimmed), failure)?;
+        let span = Span::new(&trimmed, &end);
+        Some((Node::Variable(VariableNode { sigil: dollar.is_some(), path, span }), end))
     }
 
-    fn expr(&self, state: &State) -> Option<State> {
-        let mut ret = self.term(&state);
-        if ret.is_none() { return None; }
+    fn args<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Vec<Node>> {
+        let mut args = Vec::new();
+        let mut curr = *state;
         loop {
-            let curr = self.expr_rhs(&ret.as_ref().unwrap());
-            if curr.is_none() { return ret; }
-            ret = curr;
+            let (arg, end) = match self.expr(&curr, failure) {
+                Some(arg) => arg,
+                None => return match args.len() {
+                    0 => Some((args, curr)),
+                    _ => None,
+                },
+            };
+            args.push(arg);
+            let trimmed = self.ws(&end);
+            match chr(',').matches_tracked(&trimmed, failure) {
+                Some(comma) => curr = comma,
+                None => return Some((args, end)),
+            }
         }
     }
 
-    fn condition(&self, state: &State) -> Option<State> {
-        let mut trimmed = self.ws(&state);
-        let not = chr!('!').matches(&trimmed);
-        if not.is_some() { return self.logic_factor(&not.unwrap()); }
-        let expr = self.expr(&trimmed);
-        if expr.is_none() { return None; }
-        trimmed = self.ws(&expr.unwrap());
-        let op = alt!(seq!(chr!('='), chr!('=')),
-                      seq!(chr!('!'), chr!('=')),
-                      seq!(chr!('<'), chr!('=')),
-                      seq!(chr!('>'), chr!('=')),
-                      chr!('<'),
-                      chr!('>')).matches(&trimmed);
-        if op.is_none() { return None; }
-        self.expr(&op.unwrap())
-    }
-
-    fn logic_factor(&self, state: &State) -> Option<State> {
-        let mut trimmed = self.ws(&state);
-        let cond = self.condition(&trimmed);
-        if cond.is_some() { 
-            return cond;
+    fn fcall<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let dollar = chr('$').hidden().matches_tracked(&trimmed, failure);
+        let (path, fn_name) = self.dotted_name(&dollar.unwrap_or(trimmed), failure)?;
+        let lbrace = chr('(').matches_tracked(&fn_name, failure)?;
+        let (args, end) = self.args(&lbrace, failure)?;
+        let rbrace = chr(')').matches_tracked(&self.ws(&end), failure)?;
+        let span = Span::new(&trimmed, &rbrace);
+        let node = FunctionCallNode { sigil: dollar.is_some(), path, args, span };
+        Some((Node::FunctionCall(node), rbrace))
+    }
+
+    fn list<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let lbracket = chr('[').matches_tracked(&trimmed, failure)?;
+        let (items, end) = self.args(&lbracket, failure)?;
+        let rbracket = chr(']').matches_tracked(&self.ws(&end), failure)?;
+        Some((Node::List(ListNode { items, span: Span::new(&trimmed, &rbracket) }), rbracket))
+    }
+
+    fn map<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let lbrace = chr('{').matches_tracked(&trimmed, failure)?;
+        let mut entries = Vec::new();
+        let mut curr = lbrace;
+        loop {
+            let (key, end) = match self.string(&curr, failure) {
+                Some((Node::String(key), end)) => (key.value, end),
+                _ => match entries.len() {
+                    0 => break,
+                    _ => return None,
+                },
+            };
+            let colon = chr(':').matches_tracked(&self.ws(&end), failure)?;
+            let (value, end) = self.expr(&colon, failure)?;
+            entries.push((key, value));
+            curr = end;
+            match chr(',').matches_tracked(&self.ws(&end), failure) {
+                Some(comma) => curr = comma,
+                None => break,
+            }
         }
-        let lbrace = chr!('(').matches(&trimmed);
-        if lbrace.is_none() {return None;}
-        let expr = self.logic_expr(&lbrace.unwrap());
-        if expr.is_none() { return None; }
-        trimmed = self.ws(&expr.unwrap());
-        chr!(')').matches(&trimmed)
+        let rbrace = chr('}').matches_tracked(&self.ws(&curr), failure)?;
+        Some((Node::Map(MapNode { entries, span: Span::new(&trimmed, &rbrace) }), rbrace))
     }
 
-    fn logic_term_rhs(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let op = seq!(chr!('&'), chr!('&')).matches(&trimmed);
-        if op.is_none() { return None; }
-        self.logic_factor(&op.unwrap())
+    fn id<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        self.fcall(state, failure)
+            .or_else(|| self.value(state, failure))
+            .or_else(|| self.list(state, failure))
+            .or_else(|| self.map(state, failure))
+            .or_else(|| self.var(state, failure))
     }
 
-    fn logic_term(&self, state: &State) -> Option<State> {
-        let mut ret = self.logic_factor(&state);
-        if ret.is_none() { return None; }
+    fn primary<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let (mut node, mut ret) = match self.id(&trimmed, failure) {
+            Some(id) => id,
+            None => {
+                let lbrace = chr('(').matches_tracked(&trimmed, failure)?;
+                let (node, expr) = self.expr(&lbrace, failure)?;
+                let rbrace = chr(')').matches_tracked(&self.ws(&expr), failure)?;
+                (node, rbrace)
+            },
+        };
+        // Like the arguments of a call, an index or a field must follow
+        // right away
         loop {
-            let curr = self.logic_term_rhs(&ret.as_ref().unwrap());
-            if curr.is_none() { return ret; }
-            ret = curr;
+            if let Some(lbracket) = chr('[').hidden().matches_tracked(&ret, failure) {
+                let (index, end) = self.expr(&lbracket, failure)?;
+                let rbracket = chr(']').matches_tracked(&self.ws(&end), failure)?;
+                let span = Span { start: node.span().start, end: Position::from(&rbracket) };
+                node = Node::Index(IndexNode { target: Box::new(node), index: Box::new(index), span });
+                ret = rbracket;
+            } else if let Some(dot) = chr('.').hidden().matches_tracked(&ret, failure) {
+                let (field, end) = self.name(&dot, failure)?;
+                let span = Span { start: node.span().start, end: Position::from(&end) };
+                node = Node::Field(FieldNode { target: Box::new(node), field, span });
+                ret = end;
+            } else {
+                break;
+            }
+        }
+        Some((node, ret))
+    }
+
+    // Recognizes `symbol`, where a space stands for any amount of whitespace
+    fn symbol<'a>(&self, state: &State<'a>, symbol: &str) -> Option<State<'a>> {
+        let mut curr = *state;
+        for c in symbol.chars() {
+            let next = match c {
+                ' ' => self.ws(&curr),
+                c if !curr.complete() && curr.peek() == c => curr.read(1),
+                _ => return None,
+            };
+            if next.offset() == curr.offset() { return None; }
+            curr = next;
+        }
+        // `in` must not match the beginning of `index`
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if symbol.ends_with(is_word) && !curr.complete() && is_word(curr.peek()) {
+            return None;
         }
+        Some(curr)
+    }
+
+    // Longest operator of the table at `state` that is a prefix operator,
+    // or an infix or postfix one when `prefix` is false.
+    fn operator_at<'a>(&self, state: &State<'a>, prefix: bool) -> Option<(&Operator, State<'a>)> {
+        self.operators.iter()
+            .filter(|op| (op.fixity == Fixity::Prefix) == prefix)
+            .filter_map(|op| self.symbol(state, &op.symbol).map(|end| (op, end)))
+            .max_by_key(|(_, end)| end.offset())
     }
 
-    fn logic_expr_rhs(&self, state: &State) -> Option<State> {
-        let trimmed = self.ws(&state);
-        let op = seq!(chr!('|'), chr!('|')).matches(&trimmed);
-        if op.is_none() { return None; }
-        self.logic_term(&op.unwrap())
+    // A prefix operator binding looser than its context only applies to what
+    // the context lets it take, so that `a * !b + c` is `(a * !b) + c`
+    fn operand<'a>(&self, state: &State<'a>, min_precedence: u32, failure: &mut Failure) -> Parsed<'a, Node> {
+        let trimmed = self.ws(state);
+        let (op, after) = match self.operator_at(&trimmed, true) {
+            Some(op) => op,
+            None => {
+                failure.fail(&trimmed, "unary operator");
+                return self.primary(&trimmed, failure);
+            },
+        };
+        let (operand, end) = self.expression(&after, op.precedence.max(min_precedence), failure)?;
+        let span = Span::new(&trimmed, &end);
+        Some((op.unary_node(operand, span), end))
     }
 
-    fn logic_expr(&self, state: &State) -> Option<State> {
-        let mut ret = self.logic_term(&state);
-        if ret.is_none() { return None; }
+    // Parses operators binding at least as tightly as `min_precedence`
+    fn expression<'a>(&self, state: &State<'a>, min_precedence: u32, failure: &mut Failure) -> Parsed<'a, Node> {
+        let (mut node, mut ret) = self.operand(state, min_precedence, failure)?;
         loop {
-            let curr = self.logic_expr_rhs(&ret.as_ref().unwrap());
-            if curr.is_none() { return ret; }
-            ret = curr;
+            let trimmed = self.ws(&ret);
+            let operator = self.operator_at(&trimmed, false);
+            if operator.is_none() && trimmed.peek() == '?' {
+                if precedence::TERNARY < min_precedence { break; }
+                let (then_branch, then_end) = self.expr(&trimmed.read(1), failure)?;
+                let colon = chr(':').matches_tracked(&self.ws(&then_end), failure)?;
+                let (else_branch, end) = self.expression(&colon, precedence::TERNARY, failure)?;
+                let span = node.span().to(else_branch.span());
+                node = Node::Ternary(TernaryNode {
+                    condition: Box::new(node),
+                    then_branch: Box::new(then_branch),
+                    else_branch: Box::new(else_branch),
+                    span,
+                });
+                ret = end;
+                continue;
+            }
+            let (op, after) = match operator {
+                Some(op) => op,
+                None => {
+                    failure.fail(&trimmed, "operator");
+                    break;
+                },
+            };
+            if op.precedence < min_precedence { break; }
+            match op.fixity {
+                Fixity::Infix(associativity) => {
+                    let next = match associativity {
+                        Associativity::Left => op.precedence + 1,
+                        Associativity::Right => op.precedence,
+                    };
+                    let (rhs, end) = self.expression(&after, next, failure)?;
+                    let span = node.span().to(rhs.span());
+                    node = op.infix_node(node, rhs, span);
+                    ret = end;
+                },
+                _ => {
+                    let span = Span { start: node.span().start, end: Position::from(&after) };
+                    node = op.unary_node(node, span);
+                    ret = after;
+                },
+            }
         }
+        Some((node, ret))
+    }
+
+    fn expr<'a>(&self, state: &State<'a>, failure: &mut Failure) -> Parsed<'a, Node> {
+        self.expression(state, 0, failure)
     }
 }
 
+#[cfg(test)]
 mod ts_parser {
 
 use crate::parser::Parser;
-use crate::basic_parser::State;
+use crate::basic_parser::{Failure, State};
+use crate::interpreter::{
+    BitwiseNode, BitwiseOp, BooleanNode, ComparisonOp, ConditionNode, Context, ExprNode, ExprOp,
+    FloatNode, IntegerNode, LogicExprNode, LogicTermNode, MembershipNode, Node, TermNode, TermOp, UnaryNode, UnaryOp,
+};
+use crate::interpreter::EvalError;
+use crate::parser::{precedence, Associativity, Error, Fixity, Operator, Span};
+
+// Lets the assertions below ignore the node built by a rule
+trait Remaining<'a> {
+    fn remaining(self) -> State<'a>;
+}
+
+impl<'a, T> Remaining<'a> for (T, State<'a>) {
+    fn remaining(self) -> State<'a> {
+        self.1
+    }
+}
 
 macro_rules! assert_complete {
     ($match_result:expr) => {
         assert!($match_result.is_some());
-        assert!($match_result.unwrap().complete());
+        assert!($match_result.unwrap().remaining().complete());
+    };
+}
+
+macro_rules! assert_node {
+    ($match_result:expr, $node:expr) => {
+        let ret = $match_result.unwrap();
+        assert_eq!(ret.0, $node);
+        assert!(ret.1.complete());
+    };
+}
+
+macro_rules! assert_ast {
+    ($match_result:expr, $printed:expr) => {
+        let ret = $match_result.unwrap();
+        assert_eq!(ret.0.to_string(), $printed);
+        assert!(ret.1.complete());
     };
 }
 
 macro_rules! assert_next {
     ($match_result:expr, $next:expr) => {
         assert!($match_result.is_some());
-        let ret = $match_result.unwrap();
+        let ret = $match_result.unwrap().remaining();
         assert!(!ret.complete());
         assert_eq!(ret.peek(), $next);
     };
@@ -287,106 +830,627 @@ macro_rules! assert_none {
 #[test]
 fn parse_number() {
     let p = Parser::new();
-    assert_complete!(p.number(&State::from_string(" 1")));
-    assert_complete!(p.number(&State::from_string(" 0")));
-    assert_complete!(p.number(&State::from_string(" 12034")));
-    assert_none!(p.number(&State::from_string("01234")));
+    assert_complete!(p.number(&State::from_string(" 1"), &mut Failure::new()));
+    assert_complete!(p.number(&State::from_string(" 0"), &mut Failure::new()));
+    assert_complete!(p.number(&State::from_string(" 12034"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("01234"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_float() {
+    let p = Parser::new();
+    assert_ast!(p.number(&State::from_string(" 3.14"), &mut Failure::new()), "3.14");
+    assert_ast!(p.number(&State::from_string("0.5"), &mut Failure::new()), "0.5");
+    assert_ast!(p.number(&State::from_string(".5"), &mut Failure::new()), "0.5");
+    assert_ast!(p.number(&State::from_string("1e-3"), &mut Failure::new()), "0.001");
+    assert_ast!(p.number(&State::from_string("2.5E+2"), &mut Failure::new()), "250.0");
+    assert_ast!(p.number(&State::from_string("1e3"), &mut Failure::new()), "1000.0");
+    assert_ast!(p.number(&State::from_string("99999999999999999999.0"), &mut Failure::new()), "1e20");
+    let (node, _) = p.number(&State::from_string("12"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Integer(IntegerNode { value: 12, .. })));
+    let (node, _) = p.number(&State::from_string(".25"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Float(FloatNode { value, .. }) if value == 0.25));
+    assert_next!(p.number(&State::from_string("1."), &mut Failure::new()), '.');
+    assert_next!(p.number(&State::from_string("1e"), &mut Failure::new()), 'e');
+    assert_next!(p.number(&State::from_string("1.5.2"), &mut Failure::new()), '.');
+    assert_none!(p.number(&State::from_string("00.5"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("."), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("1e999"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_radix_and_separators() {
+    let p = Parser::new();
+    assert_ast!(p.number(&State::from_string(" 0x1F"), &mut Failure::new()), "31");
+    assert_ast!(p.number(&State::from_string("0Xff_ff"), &mut Failure::new()), "65535");
+    assert_ast!(p.number(&State::from_string("0x1e3"), &mut Failure::new()), "483");
+    assert_ast!(p.number(&State::from_string("0o17"), &mut Failure::new()), "15");
+    assert_ast!(p.number(&State::from_string("0b1010"), &mut Failure::new()), "10");
+    assert_ast!(p.number(&State::from_string("0B1111_0000"), &mut Failure::new()), "240");
+    assert_ast!(p.number(&State::from_string("1_000_000"), &mut Failure::new()), "1000000");
+    assert_ast!(p.number(&State::from_string("0x7fff_ffff_ffff_ffff"), &mut Failure::new()), "9223372036854775807");
+    assert_ast!(p.number(&State::from_string("1_000.000_5"), &mut Failure::new()), "1000.0005");
+    assert_ast!(p.number(&State::from_string("1_0e1_0"), &mut Failure::new()), "100000000000.0");
+    assert_next!(p.number(&State::from_string("1__0"), &mut Failure::new()), '_');
+    assert_next!(p.number(&State::from_string("1_"), &mut Failure::new()), '_');
+    assert_next!(p.number(&State::from_string("0b102"), &mut Failure::new()), '2');
+    assert_next!(p.number(&State::from_string("0x1.5"), &mut Failure::new()), '.');
+    assert_next!(p.number(&State::from_string("1._5"), &mut Failure::new()), '.');
+    assert_none!(p.number(&State::from_string("0x"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("0x_1"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("0o8"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("0_1"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("_1"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("0x8000_0000_0000_0000"), &mut Failure::new()));
+    assert_none!(p.number(&State::from_string("0b1111111111111111111111111111111111111111111111111111111111111111"), &mut Failure::new()));
+    let err = p.parse_ast("0x1G > 1").unwrap_err();
+    assert_eq!(err.to_string(), "expected operator or end of input at 1:4, found 'G'");
+    let err = p.parse_ast("0o > 1").unwrap_err();
+    assert_eq!(err.to_string(), "expected octal digit at 1:3, found ' '");
+    let err = p.parse_ast("1_000_ > 1").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit at 1:7, found ' '");
+    let err = p.parse_ast("0xFFFF_FFFF_FFFF_FFFF > 1").unwrap_err();
+    assert_eq!(err.to_string(), "integer literal out of range at 1:1");
+    assert_eq!(err.span.end.offset, 21);
+    // Not merged with the other alternatives at the same position
+    let err = p.parse_ast("-9223372036854775808").unwrap_err();
+    assert_eq!(err.to_string(), "integer literal out of range at 1:2");
+    let err = p.parse_ast("1e999 > 1").unwrap_err();
+    assert_eq!(err.to_string(), "number literal out of range at 1:1");
+}
+
+#[test]
+fn parse_string() {
+    let p = Parser::new();
+    let string = |s| match p.string(&State::from_string(s), &mut Failure::new()) {
+        Some((Node::String(n), end)) if end.complete() => Some(n.value),
+        _ => None,
+    };
+    assert_eq!(string(r#" "admin""#), Some("admin".to_string()));
+    assert_eq!(string("'admin'"), Some("admin".to_string()));
+    assert_eq!(string(r#""""#), Some("".to_string()));
+    assert_eq!(string(r#""it's""#), Some("it's".to_string()));
+    assert_eq!(string(r#"'say "hi"'"#), Some("say \"hi\"".to_string()));
+    assert_eq!(string(r#""a\"b\'c\\d""#), Some("a\"b'c\\d".to_string()));
+    assert_eq!(string(r#""\n\t\r\0""#), Some("\n\t\r\0".to_string()));
+    assert_eq!(string(r#""caf\u{e9} \u{1F600}""#), Some("café 😀".to_string()));
+    assert_eq!(string("'città'"), Some("città".to_string()));
+    assert_eq!(string(r#""unterminated"#), None);
+    assert_eq!(string(r#""bad \q escape""#), None);
+    assert_eq!(string(r#""\u{110000}""#), None);
+    assert_eq!(string(r#""\u{zz}""#), None);
+    assert_eq!(string(r#""\u41""#), None);
+    assert_ast!(p.value(&State::from_string("'a\"b'"), &mut Failure::new()), r#""a\"b""#);
+}
+
+#[test]
+fn parse_string_error() {
+    let p = Parser::new();
+    let err = p.parse_ast(r#"$role == "admin"#).unwrap_err();
+    assert_eq!((err.span.start.column, err.found), (16, None));
+    assert_eq!(err.expected, vec!["'\"'"]);
+    let err = p.parse_ast(r#"$role == 'ad\min'"#).unwrap_err();
+    assert_eq!((err.span.start.column, err.found), (14, Some('m')));
+    assert_eq!(err.to_string(), "expected escape sequence at 1:14, found 'm'");
+    // Reported over the hexadecimal digits that could have gone on
+    let err = p.parse_ast(r#"'\u{d800}' == x"#).unwrap_err();
+    assert_eq!(err.to_string(), "invalid unicode escape at 1:5");
+    let err = p.parse_ast(r#"'\u{110000}'"#).unwrap_err();
+    assert_eq!(err.to_string(), "invalid unicode escape at 1:5");
+    let err = p.parse_ast(r#"x == "a\u{FFFFFFFFFFF}""#).unwrap_err();
+    assert_eq!(err.to_string(), "invalid unicode escape at 1:11");
+    assert_eq!(err.span.end.column, 23);
+}
+
+#[test]
+fn parse_literal() {
+    let p = Parser::new();
+    assert_ast!(p.value(&State::from_string(" true"), &mut Failure::new()), "true");
+    assert_ast!(p.value(&State::from_string("false"), &mut Failure::new()), "false");
+    assert_ast!(p.value(&State::from_string("null"), &mut Failure::new()), "null");
+    let (node, _) = p.value(&State::from_string("true"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Boolean(BooleanNode { value: true, .. })));
+    assert_none!(p.literal(&State::from_string("truest"), &mut Failure::new()));
+    assert_none!(p.literal(&State::from_string("nil"), &mut Failure::new()));
+    assert_none!(p.name(&State::from_string("null"), &mut Failure::new()));
+    assert_none!(p.var(&State::from_string("$true"), &mut Failure::new()));
+    assert_none!(p.var(&State::from_string("a.false"), &mut Failure::new()));
+    assert_complete!(p.var(&State::from_string("nullable"), &mut Failure::new()));
+    assert_ast!(p.id(&State::from_string("false_positive"), &mut Failure::new()), "false_positive");
+    assert_ast!(p.expr(&State::from_string("$flag == true || !x && null != y"), &mut Failure::new()),
+                "(($flag == true) || (!x && (null != y)))");
+    let err = p.parse_ast("$true").unwrap_err();
+    assert_eq!(err.to_string(), "expected name at 1:2, found 't'");
 }
 
 #[test]
 fn parse_name() {
     let p = Parser::new();
-    assert_complete!(p.name(&State::from_string("n")));
-    assert_complete!(p.name(&State::from_string("name")));
-    assert_complete!(p.name(&State::from_string("complex_name0")));
-    assert_none!(p.name(&State::from_string("_name")));
-    assert_none!(p.name(&State::from_string("0name")));
+    assert_complete!(p.name(&State::from_string("n"), &mut Failure::new()));
+    assert_complete!(p.name(&State::from_string("name"), &mut Failure::new()));
+    assert_complete!(p.name(&State::from_string("complex_name0"), &mut Failure::new()));
+    assert_none!(p.name(&State::from_string("_name"), &mut Failure::new()));
+    assert_none!(p.name(&State::from_string("0name"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_unicode_name() {
+    let p = Parser::new().unicode_identifiers(true);
+    assert_node!(p.name(&State::from_string("città"), &mut Failure::new()), "città".to_string());
+    assert_complete!(p.name(&State::from_string("größe_2"), &mut Failure::new()));
+    assert_complete!(p.name(&State::from_string("変数"), &mut Failure::new()));
+    assert_none!(p.name(&State::from_string("_name"), &mut Failure::new()));
+    assert_none!(p.name(&State::from_string("2größe"), &mut Failure::new()));
+    let p = Parser::new();
+    assert_none!(p.name(&State::from_string("città").read(4), &mut Failure::new()));
+    let (name, state) = p.name(&State::from_string("città"), &mut Failure::new()).unwrap();
+    assert_eq!((name.as_str(), state.peek()), ("citt", 'à'));
 }
 
 #[test]
 fn parse_dotted_name() {
     let p = Parser::new();
-    assert_complete!(p.dotted_name(&State::from_string("dotted.name.var")));
-    assert_none!(p.dotted_name(&State::from_string(".invalid.name")));
+    assert_complete!(p.dotted_name(&State::from_string("dotted.name.var"), &mut Failure::new()));
+    assert_none!(p.dotted_name(&State::from_string(".invalid.name"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_var() {
     let p = Parser::new();
-    assert_complete!(p.var(&State::from_string("var")));
-    assert_complete!(p.var(&State::from_string("$dotted.var")));
+    assert_complete!(p.var(&State::from_string("var"), &mut Failure::new()));
+    assert_complete!(p.var(&State::from_string("$dotted.var"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_fcall() {
     let p = Parser::new();
-    assert_complete!(p.fcall(&State::from_string("fn(arg)")));
-    assert_complete!(p.fcall(&State::from_string("fn()")));
-    assert_complete!(p.fcall(&State::from_string("$fn(arg0, arg1)")));
-    assert_complete!(p.fcall(&State::from_string("$fn($arg0(z), 21)")));
+    assert_complete!(p.fcall(&State::from_string("fn(arg)"), &mut Failure::new()));
+    assert_complete!(p.fcall(&State::from_string("fn()"), &mut Failure::new()));
+    assert_complete!(p.fcall(&State::from_string("$fn(arg0, arg1)"), &mut Failure::new()));
+    assert_complete!(p.fcall(&State::from_string("$fn($arg0(z), 21)"), &mut Failure::new()));
+    assert_complete!(p.fcall(&State::from_string("fn( )"), &mut Failure::new()));
+    assert_ast!(p.fcall(&State::from_string("max($a + 1, 2 * $b)"), &mut Failure::new()), "max(($a + 1), (2 * $b))");
+    assert_ast!(p.fcall(&State::from_string("f(x > 3)"), &mut Failure::new()), "f((x > 3))");
+    assert_ast!(p.fcall(&State::from_string("f( a && !b ,c||d ,\n -(1) )"), &mut Failure::new()), "f((a && !b), (c || d), -1)");
+    assert_ast!(p.fcall(&State::from_string("f(g(h(1), (2 + 3)) % 2, 'a')"), &mut Failure::new()), "f((g(h(1), (2 + 3)) % 2), \"a\")");
+    assert_ast!(p.fcall(&State::from_string("f(x ? 1 : 2)"), &mut Failure::new()), "f((x ? 1 : 2))");
+    assert_next!(p.fcall(&State::from_string("f(1) g"), &mut Failure::new()), ' ');
+    assert_none!(p.fcall(&State::from_string("f(1,)"), &mut Failure::new()));
+    assert_none!(p.fcall(&State::from_string("f(, 1)"), &mut Failure::new()));
+    assert_none!(p.fcall(&State::from_string("f(1 +)"), &mut Failure::new()));
 }
 
 #[test]
 fn id() {
     let p = Parser::new();
-    assert_complete!(p.id(&State::from_string("var")));
-    assert_complete!(p.id(&State::from_string("$var")));
-    assert_complete!(p.id(&State::from_string("fn(arg)")));
-    assert_complete!(p.id(&State::from_string("$fn(arg)")));
-    assert_complete!(p.id(&State::from_string("0")));
+    assert_complete!(p.id(&State::from_string("var"), &mut Failure::new()));
+    assert_complete!(p.id(&State::from_string("$var"), &mut Failure::new()));
+    assert_complete!(p.id(&State::from_string("fn(arg)"), &mut Failure::new()));
+    assert_complete!(p.id(&State::from_string("$fn(arg)"), &mut Failure::new()));
+    assert_complete!(p.id(&State::from_string("0"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_factor() {
     let p = Parser::new();
-    assert_complete!(p.factor(&State::from_string("42")));
-    assert_complete!(p.factor(&State::from_string(" ( 42+21 )")));
-    assert_complete!(p.factor(&State::from_string(" ( $var )")));
+    assert_complete!(p.primary(&State::from_string("42"), &mut Failure::new()));
+    assert_complete!(p.primary(&State::from_string(" ( 42+21 )"), &mut Failure::new()));
+    assert_complete!(p.primary(&State::from_string(" ( $var )"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_unary() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("-5"), &mut Failure::new()), "-5");
+    assert_ast!(p.expr(&State::from_string("+ 5"), &mut Failure::new()), "+5");
+    assert_ast!(p.expr(&State::from_string("--x"), &mut Failure::new()), "--x");
+    assert_ast!(p.expr(&State::from_string("-$x * 2"), &mut Failure::new()), "(-$x * 2)");
+    assert_ast!(p.expr(&State::from_string("3 - -1"), &mut Failure::new()), "(3 - -1)");
+    assert_ast!(p.expr(&State::from_string("3--1"), &mut Failure::new()), "(3 - -1)");
+    assert_ast!(p.expr(&State::from_string("-(1 + 2) % -f(1)"), &mut Failure::new()), "(-(1 + 2) % -f(1))");
+    assert_ast!(p.expr(&State::from_string("$x > -5"), &mut Failure::new()), "($x > -5)");
+    let (node, _) = p.expr(&State::from_string("-1.5"), &mut Failure::new()).unwrap();
+    match node {
+        Node::Unary(n) => {
+            assert_eq!(n.op, UnaryOp::Minus);
+            assert!(matches!(*n.operand, Node::Float(FloatNode { value, .. }) if value == 1.5));
+            assert_eq!((n.span.start.offset, n.span.end.offset), (0, 4));
+        },
+        _ => panic!("not a unary operation: {:?}", node),
+    }
+    assert_none!(p.expr(&State::from_string("-"), &mut Failure::new()));
+    assert_none!(p.expr(&State::from_string("-'a"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_term() {
     let p = Parser::new();
-    assert_complete!(p.term(&State::from_string("42 * 21")));
-    assert_complete!(p.term(&State::from_string("42 / 21")));
-    assert_complete!(p.term(&State::from_string("42 % 21")));
-    assert_complete!(p.term(&State::from_string("42")));
-    assert_complete!(p.term(&State::from_string("(1 + 2) * (3 + 4 + 5) / 6")));
+    assert_complete!(p.expr(&State::from_string("42 * 21"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("42 / 21"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("42 % 21"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("42"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("(1 + 2) * (3 + 4 + 5) / 6"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_expr() {
     let p = Parser::new();
-    assert_complete!(p.expr(&State::from_string("31")));
-    assert_complete!(p.expr(&State::from_string("31 * 91")));
-    assert_complete!(p.expr(&State::from_string("31 * 91 + 21")));
-    assert_complete!(p.expr(&State::from_string("31 * 91 + 21 - 51")));
-    assert_complete!(p.expr(&State::from_string("31 * 91 + 21 - 51/41 % 21")));
-    assert_complete!(p.expr(&State::from_string("1 + 2  +3")));
+    assert_complete!(p.expr(&State::from_string("31"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("31 * 91"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("31 * 91 + 21"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("31 * 91 + 21 - 51"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("31 * 91 + 21 - 51/41 % 21"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("1 + 2  +3"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_condition() {
     let p = Parser::new();
-    assert_complete!(p.condition(&State::from_string("2 + 42 < 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("2 + 42 <= 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("2 + 42 > 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("2 + 42 >= 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("2 + 42 == 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("2 + 42 != 15 - 3")));
-    assert_complete!(p.condition(&State::from_string("!(2 > 3)")));
+    assert_complete!(p.expr(&State::from_string("2 + 42 < 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("2 + 42 <= 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("2 + 42 > 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("2 + 42 >= 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("2 + 42 == 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("2 + 42 != 15 - 3"), &mut Failure::new()));
+    assert_complete!(p.expr(&State::from_string("!(2 > 3)"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_logic_term() {
     let p = Parser::new();
-    assert_complete!(p.logic_term(&State::from_string("2 < 3 && 5 > 4")));
+    assert_complete!(p.expr(&State::from_string("2 < 3 && 5 > 4"), &mut Failure::new()));
 }
 
 #[test]
 fn parse_logic_expr() {
     let p = Parser::new();
-    assert_complete!(p.logic_expr(&State::from_string("2 < 3 || 5 > 4")));
+    assert_complete!(p.expr(&State::from_string("2 < 3 || 5 > 4"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_ternary() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("$vip ? 10 : 5"), &mut Failure::new()), "($vip ? 10 : 5)");
+    assert_ast!(p.expr(&State::from_string("x > 1 && y ? a + 1 : -b * 2"), &mut Failure::new()),
+                "(((x > 1) && y) ? (a + 1) : (-b * 2))");
+    assert_ast!(p.expr(&State::from_string("a ? (b ? 1 : 2) : (c ? 3 : 4)"), &mut Failure::new()),
+                "(a ? (b ? 1 : 2) : (c ? 3 : 4))");
+    assert_ast!(p.expr(&State::from_string("(a ? 1 : 2) > 1 || (b)"), &mut Failure::new()), "(((a ? 1 : 2) > 1) || b)");
+    assert_ast!(p.expr(&State::from_string("x == 1"), &mut Failure::new()), "(x == 1)");
+    assert_none!(p.expr(&State::from_string("a ? 1"), &mut Failure::new()));
+    assert_ast!(p.expr(&State::from_string("a ? b > 1 : c || d"), &mut Failure::new()), "(a ? (b > 1) : (c || d))");
+    assert_ast!(p.expr(&State::from_string("a ? 1 : b ? 2 : 3"), &mut Failure::new()), "(a ? 1 : (b ? 2 : 3))");
+    assert_ast!(p.expr(&State::from_string("a ? b ? 1 : 2 : 3"), &mut Failure::new()), "(a ? (b ? 1 : 2) : 3)");
+    assert_none!(p.expr(&State::from_string("a ? 1 :"), &mut Failure::new()));
+    let err = p.parse_ast("a ? 1 2").unwrap_err();
+    assert_eq!((err.span.start.column, err.found), (7, Some('2')));
+    assert_eq!(err.expected, vec!["operator", "':'"]);
+}
+
+#[test]
+fn parse_list() {
+    let p = Parser::new();
+    assert_ast!(p.list(&State::from_string(" [1, 2, $x]"), &mut Failure::new()), "[1, 2, $x]");
+    assert_ast!(p.list(&State::from_string("[ ]"), &mut Failure::new()), "[]");
+    assert_ast!(p.list(&State::from_string("[[1], ['a', f(x + 1)], x > 1 ? 1 : 2]"), &mut Failure::new()),
+                "[[1], [\"a\", f((x + 1))], ((x > 1) ? 1 : 2)]");
+    assert_ast!(p.expr(&State::from_string("$items[0]"), &mut Failure::new()), "$items[0]");
+    assert_ast!(p.expr(&State::from_string("$matrix[i][j + 1]"), &mut Failure::new()), "$matrix[i][(j + 1)]");
+    assert_ast!(p.expr(&State::from_string("[1, 2][0] + f(x)[1] * (a)[ 2 ]"), &mut Failure::new()), "([1, 2][0] + (f(x)[1] * a[2]))");
+    assert_ast!(p.expr(&State::from_string("-a[0]"), &mut Failure::new()), "-a[0]");
+    let (node, _) = p.expr(&State::from_string("a.b[1]"), &mut Failure::new()).unwrap();
+    match node {
+        Node::Index(n) => {
+            assert!(matches!(*n.target, Node::Variable(_)));
+            assert!(matches!(*n.index, Node::Integer(IntegerNode { value: 1, .. })));
+        },
+        _ => panic!("not an index: {:?}", node),
+    }
+    assert_next!(p.expr(&State::from_string("a [0]"), &mut Failure::new()), ' ');
+    assert_none!(p.list(&State::from_string("[1, 2"), &mut Failure::new()));
+    assert_none!(p.list(&State::from_string("[1,]"), &mut Failure::new()));
+    assert_none!(p.expr(&State::from_string("a[]"), &mut Failure::new()));
+    let err = p.parse_ast("$items[0").unwrap_err();
+    assert_eq!(err.to_string(), "expected operator or ']' at 1:9, found end of input");
+}
+
+#[test]
+fn parse_map() {
+    let p = Parser::new();
+    assert_ast!(p.map(&State::from_string(r#" {"a": 1, 'b': $x}"#), &mut Failure::new()), r#"{"a": 1, "b": $x}"#);
+    assert_ast!(p.map(&State::from_string("{ }"), &mut Failure::new()), "{}");
+    assert_ast!(p.map(&State::from_string(r#"{"Content-Type": "json", "": [1], "a b": {"c": x ? 1 : 2}}"#), &mut Failure::new()),
+                r#"{"Content-Type": "json", "": [1], "a b": {"c": (x ? 1 : 2)}}"#);
+    assert_ast!(p.expr(&State::from_string(r#"$headers["Content-Type"]"#), &mut Failure::new()), r#"$headers["Content-Type"]"#);
+    assert_ast!(p.expr(&State::from_string(r#"$a.b["c d"][0]"#), &mut Failure::new()), r#"$a.b["c d"][0]"#);
+    assert_ast!(p.expr(&State::from_string(r#"{"a": 1}["a"] + 1"#), &mut Failure::new()), r#"({"a": 1}["a"] + 1)"#);
+    assert_none!(p.map(&State::from_string("{a: 1}"), &mut Failure::new()));
+    assert_none!(p.map(&State::from_string(r#"{"a" 1}"#), &mut Failure::new()));
+    assert_none!(p.map(&State::from_string(r#"{"a": 1,}"#), &mut Failure::new()));
+    assert_none!(p.map(&State::from_string(r#"{"a": 1"#), &mut Failure::new()));
+    let err = p.parse_ast(r#"{"a": 1 "b": 2}"#).unwrap_err();
+    assert_eq!(err.to_string(), "expected operator, ',' or '}' at 1:9, found '\"'");
+}
+
+#[test]
+fn parse_field() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("$items[0].name"), &mut Failure::new()), "$items[0].name");
+    assert_ast!(p.expr(&State::from_string(r#"$m["a"].b"#), &mut Failure::new()), r#"$m["a"].b"#);
+    assert_ast!(p.expr(&State::from_string("[1][0].x"), &mut Failure::new()), "[1][0].x");
+    assert_ast!(p.expr(&State::from_string("a.b.c(1)[0].d"), &mut Failure::new()), "a.b.c(1)[0].d");
+    assert_ast!(p.expr(&State::from_string("f(x).a.b[1] + 1"), &mut Failure::new()), "(f(x).a.b[1] + 1)");
+    let (node, _) = p.expr(&State::from_string("a.b.c(1)[0].d"), &mut Failure::new()).unwrap();
+    match node {
+        Node::Field(n) => {
+            assert_eq!(n.field, "d");
+            assert!(matches!(*n.target, Node::Index(_)));
+        },
+        _ => panic!("not a field: {:?}", node),
+    }
+    assert_next!(p.expr(&State::from_string("a[0] .b"), &mut Failure::new()), ' ');
+    assert_none!(p.expr(&State::from_string("a[0].true"), &mut Failure::new()));
+    let err = p.parse_ast("$items[0].").unwrap_err();
+    assert_eq!(err.to_string(), "expected name at 1:11, found end of input");
+}
+
+#[test]
+fn parse_precedence() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("(a > 1) == (b > 2)"), &mut Failure::new()), "((a > 1) == (b > 2))");
+    assert_ast!(p.expr(&State::from_string("a + b * c - d"), &mut Failure::new()), "((a + (b * c)) - d)");
+    assert_ast!(p.expr(&State::from_string("a || b && c == d + -e"), &mut Failure::new()), "(a || (b && (c == (d + -e))))");
+    assert_ast!(p.expr(&State::from_string("!a > 1 && !b"), &mut Failure::new()), "(!(a > 1) && !b)");
+    assert_ast!(p.expr(&State::from_string("a < b < c"), &mut Failure::new()), "((a < b) < c)");
+    assert_ast!(p.expr(&State::from_string("a ? b : c || d"), &mut Failure::new()), "(a ? b : (c || d))");
+    assert_ast!(p.expr(&State::from_string("x<-1"), &mut Failure::new()), "(x < -1)");
+    assert_ast!(p.expr(&State::from_string("x!=-1"), &mut Failure::new()), "(x != -1)");
+    assert_ast!(p.expr(&State::from_string("a * !b + c"), &mut Failure::new()), "((a * !b) + c)");
+    assert_ast!(p.expr(&State::from_string("1 + !x == 7"), &mut Failure::new()), "((1 + !x) == 7)");
+    assert_none!(p.expr(&State::from_string("1 + * 2"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_membership() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string(r#"$country in ["IT", "FR"]"#), &mut Failure::new()), r#"($country in ["IT", "FR"])"#);
+    assert_ast!(p.expr(&State::from_string("x not in y"), &mut Failure::new()), "(x not in y)");
+    assert_ast!(p.expr(&State::from_string("x not\n  in y"), &mut Failure::new()), "(x not in y)");
+    assert_ast!(p.expr(&State::from_string("a + 1 in b && !(c in d)"), &mut Failure::new()), "(((a + 1) in b) && !(c in d))");
+    assert_ast!(p.expr(&State::from_string("index in inputs"), &mut Failure::new()), "(index in inputs)");
+    assert_ast!(p.expr(&State::from_string("notes in nothing"), &mut Failure::new()), "(notes in nothing)");
+    let (node, _) = p.expr(&State::from_string("a not in b"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Membership(MembershipNode { negated: true, .. })));
+    assert_next!(p.expr(&State::from_string("x inside"), &mut Failure::new()), ' ');
+    assert_next!(p.expr(&State::from_string("x notin y"), &mut Failure::new()), ' ');
+    assert_next!(p.expr(&State::from_string("x not y"), &mut Failure::new()), ' ');
+    assert_none!(p.expr(&State::from_string("x in"), &mut Failure::new()));
+}
+
+#[test]
+fn parse_bitwise() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("$flags & 4 != 0"), &mut Failure::new()), "(($flags & 4) != 0)");
+    assert_ast!(p.expr(&State::from_string("a | b ^ c & d"), &mut Failure::new()), "(a | (b ^ (c & d)))");
+    assert_ast!(p.expr(&State::from_string("a & b << 1 + c"), &mut Failure::new()), "(a & (b << (1 + c)))");
+    assert_ast!(p.expr(&State::from_string("a >> 1 >> 2"), &mut Failure::new()), "((a >> 1) >> 2)");
+    assert_ast!(p.expr(&State::from_string("~a & ~-b"), &mut Failure::new()), "(~a & ~-b)");
+    assert_ast!(p.expr(&State::from_string("a&&b&c||d|e"), &mut Failure::new()), "((a && (b & c)) || (d | e))");
+    assert_none!(p.expr(&State::from_string("a & &b"), &mut Failure::new()));
+    let (node, _) = p.expr(&State::from_string("a ^ b"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Bitwise(BitwiseNode { op: BitwiseOp::Xor, .. })));
+    assert_ast!(p.expr(&State::from_string("x<<1 <= y>>1"), &mut Failure::new()), "((x << 1) <= (y >> 1))");
+    assert_none!(p.expr(&State::from_string("a & "), &mut Failure::new()));
+}
+
+#[test]
+#[should_panic(expected = "invalid operator symbol")]
+fn operator_double_space() {
+    Operator::infix("not  in", precedence::COMPARISON, Associativity::Left, "not_in");
+}
+
+#[test]
+#[should_panic(expected = "invalid operator symbol")]
+fn operator_inner_tab() {
+    Operator::prefix("not\tx", precedence::NOT, "not");
+}
+
+#[test]
+fn parser_is_shareable() {
+    fn assert_sync<T: Send + Sync>(_: &T) {}
+    let p = Parser::new().unicode_identifiers(true);
+    assert_sync(&p);
+    std::thread::spawn(move || assert!(p.parse_ast("x > 1").is_ok())).join().unwrap();
+}
+
+#[test]
+fn parse_custom_operators() {
+    let p = Parser::new()
+        .operator(Operator::infix("**", precedence::UNARY + 10, Associativity::Right, "pow"))
+        .operator(Operator::postfix("!", precedence::UNARY + 10, "fact"))
+        .operator(Operator::infix("div", precedence::PRODUCT, Associativity::Left, "div"))
+        .operator(Operator::prefix("not", precedence::NOT, "not"))
+        .operator(Operator::infix("+", precedence::SUM, Associativity::Left, "concat"));
+    assert_ast!(p.expr(&State::from_string("2 ** 3 ** 2"), &mut Failure::new()), "(2 ** (3 ** 2))");
+    assert_ast!(p.expr(&State::from_string("-2 ** 2 * 3"), &mut Failure::new()), "(-(2 ** 2) * 3)");
+    assert_ast!(p.expr(&State::from_string("x! != 3!"), &mut Failure::new()), "(x! != 3!)");
+    assert_ast!(p.expr(&State::from_string("!x!"), &mut Failure::new()), "!x!");
+    assert_ast!(p.expr(&State::from_string("dividend div divisor * 2"), &mut Failure::new()), "((dividend div divisor) * 2)");
+    assert_ast!(p.expr(&State::from_string("not x && nothing"), &mut Failure::new()), "(not x && nothing)");
+    assert_next!(p.expr(&State::from_string("a divisor"), &mut Failure::new()), ' ');
+    let (node, _) = p.expr(&State::from_string("1 + 2"), &mut Failure::new()).unwrap();
+    match node {
+        Node::Operator(n) => {
+            assert_eq!((n.symbol.as_str(), n.function.as_str()), ("+", "concat"));
+            assert_eq!(n.fixity, Fixity::Infix(Associativity::Left));
+            assert_eq!(n.operands.len(), 2);
+        },
+        _ => panic!("not an operator: {:?}", node),
+    }
+    let (node, _) = p.expr(&State::from_string("-1"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Unary(UnaryNode { op: UnaryOp::Minus, .. })));
+    let (node, _) = Parser::new().expr(&State::from_string("1 + 2"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Expr(ExprNode { op: ExprOp::Add, .. })));
+    let s = "n! + 1";
+    let node = p.parse_ast(s).unwrap();
+    let fact = match &node {
+        Node::Operator(n) => &n.operands[0],
+        _ => panic!("unexpected node {}", node),
+    };
+    assert_eq!(spanned(s, fact.span()), "n!");
+}
+
+// Returns the text covered by a span
+fn spanned(s: &str, span: Span) -> &str {
+    &s[span.start.offset..span.end.offset]
+}
+
+#[test]
+fn ast_leaves() {
+    let p = Parser::new();
+    assert_ast!(p.number(&State::from_string(" 42"), &mut Failure::new()), "42");
+    assert_ast!(p.var(&State::from_string("$a.b.c"), &mut Failure::new()), "$a.b.c");
+    assert_ast!(p.var(&State::from_string("a.b.c"), &mut Failure::new()), "a.b.c");
+    assert_ast!(p.fcall(&State::from_string("$fn.x(1, y)"), &mut Failure::new()), "$fn.x(1, y)");
+    let (node, _) = p.var(&State::from_string("$a.b.c"), &mut Failure::new()).unwrap();
+    match node {
+        Node::Variable(v) => {
+            assert!(v.sigil);
+            assert_eq!(v.path, vec!["a", "b", "c"]);
+        },
+        _ => panic!("not a variable: {:?}", node),
+    }
+    let (node, _) = p.fcall(&State::from_string("f(1, y)"), &mut Failure::new()).unwrap();
+    match node {
+        Node::FunctionCall(f) => {
+            assert!(!f.sigil);
+            assert_eq!(f.path, vec!["f"]);
+            assert!(matches!(f.args[0], Node::Integer(IntegerNode { value: 1, .. })));
+            assert!(matches!(f.args[1], Node::Variable(_)));
+        },
+        _ => panic!("not a function call: {:?}", node),
+    }
+    assert_none!(p.number(&State::from_string("99999999999999999999"), &mut Failure::new()));
+}
+
+#[test]
+fn ast_expr() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("1 + 2 * 3"), &mut Failure::new()), "(1 + (2 * 3))");
+    assert_ast!(p.expr(&State::from_string("10 - 4 - 3"), &mut Failure::new()), "((10 - 4) - 3)");
+    assert_ast!(p.expr(&State::from_string("(1 + x) % f(2)"), &mut Failure::new()), "((1 + x) % f(2))");
+    let (node, _) = p.expr(&State::from_string("7 / 2"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Term(TermNode { op: TermOp::Div, .. })));
+    let (node, _) = p.expr(&State::from_string("7 - 2"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Expr(ExprNode { op: ExprOp::Sub, .. })));
+}
+
+#[test]
+fn ast_logic_expr() {
+    let p = Parser::new();
+    assert_ast!(p.expr(&State::from_string("1 == 2 || 2 <= 2 && !(3 != 4)"), &mut Failure::new()),
+                "((1 == 2) || ((2 <= 2) && !(3 != 4)))");
+    let (node, _) = p.expr(&State::from_string("1 >= 2"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::Condition(ConditionNode { op: ComparisonOp::Ge, .. })));
+    let (node, _) = p.expr(&State::from_string("1 > 2 && 2 > 1"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::LogicTerm(LogicTermNode { .. })));
+    let (node, _) = p.expr(&State::from_string("1 > 2 || 2 > 1"), &mut Failure::new()).unwrap();
+    assert!(matches!(node, Node::LogicExpr(LogicExprNode { .. })));
+}
+
+#[test]
+fn spans() {
+    let p = Parser::new();
+    let s = "$a.b + f(1,\n  x) > 3 && !(y == 2)";
+    let node = p.parse_ast(s).unwrap();
+    assert_eq!(spanned(s, node.span()), s);
+    let (cond, not) = match &node {
+        Node::LogicTerm(n) => (&*n.lhs, &*n.rhs),
+        _ => panic!("unexpected node {}", node),
+    };
+    assert_eq!(spanned(s, not.span()), "!(y == 2)");
+    let (sum, three) = match cond {
+        Node::Condition(n) => (&*n.lhs, &*n.rhs),
+        _ => panic!("unexpected node {}", cond),
+    };
+    assert_eq!(spanned(s, three.span()), "3");
+    assert_eq!(spanned(s, sum.span()), "$a.b + f(1,\n  x)");
+    let (var, call) = match sum {
+        Node::Expr(n) => (&*n.lhs, &*n.rhs),
+        _ => panic!("unexpected node {}", sum),
+    };
+    assert_eq!(spanned(s, var.span()), "$a.b");
+    assert_eq!(spanned(s, call.span()), "f(1,\n  x)");
+    assert_eq!((call.span().start.line, call.span().start.column), (1, 8));
+    assert_eq!((call.span().end.line, call.span().end.column), (2, 5));
+    let x = match call {
+        Node::FunctionCall(n) => &n.args[1],
+        _ => panic!("unexpected node {}", call),
+    };
+    assert_eq!((x.span().start.offset, x.span().start.line, x.span().start.column), (14, 2, 3));
+}
+
+#[test]
+fn parse() {
+    let p = Parser::new();
+    let mut ctx = Context::new();
+    ctx.set("a.b", 2);
+    ctx.set("x", 1);
+    assert_eq!(p.parse("$a.b + 2 > 3 && !(x == 1)", &ctx), Ok(false));
+    assert_eq!(p.parse("$a.b + 2 > 3 && !(x == 2) ", &ctx), Ok(true));
+    assert!(matches!(p.parse("$a.b + 2 > 3 &&", &ctx), Err(Error::Parse(_))));
+    assert!(matches!(p.parse("$unknown > 3", &ctx), Err(Error::Eval(EvalError::UnknownVariable { .. }))));
+    let err = p.parse("$a.b / (x - 1) > 0", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "division by zero at 1:1");
+    assert!(p.parse_ast("$unknown > 3").is_ok());
+    assert!(p.parse_ast("1 +").is_err());
+}
+
+#[test]
+fn parse_unicode() {
+    let mut ctx = Context::new();
+    ctx.set("utente.età", 42);
+    let p = Parser::new().unicode_identifiers(true);
+    assert_eq!(p.parse("$utente.età >= 18", &ctx), Ok(true));
+    let err = Parser::new().parse_ast("é > 1").unwrap_err();
+    assert_eq!((err.span.start.offset, err.span.start.column, err.found), (0, 1, Some('é')));
+    let err = Parser::new().parse_ast("«1» > 1").unwrap_err();
+    assert_eq!(err.found, Some('«'));
+    let err = p.parse_ast("età > 1 ¬").unwrap_err();
+    assert_eq!((err.span.start.offset, err.span.start.column, err.found), (9, 9, Some('¬')));
+}
+
+#[test]
+fn parse_long_input() {
+    let p = Parser::new();
+    let mut s = "1".to_string();
+    for _ in 0..2000 {
+        s.push_str(" +\n 1");
+    }
+    s.push_str(" > 0");
+    assert!(p.parse_ast(&s).is_ok());
+    let err = p.parse_ast(&s[..s.len() - 2]).unwrap_err();
+    assert_eq!((err.span.start.line, err.span.start.column), (2001, 5));
+}
+
+#[test]
+fn parse_error() {
+    let p = Parser::new();
+    let err = p.parse_ast("(1 + 2 x > 3").unwrap_err();
+    assert_eq!((err.span.start.offset, err.span.start.line, err.span.start.column, err.found), (7, 1, 8, Some('x')));
+    assert_eq!(err.span.end.offset, 8);
+    assert_eq!(err.expected, vec!["operator", "')'"]);
+    assert_eq!(err.to_string(), "expected operator or ')' at 1:8, found 'x'");
+    let err = p.parse_ast("1 > 2 &&\n  fn(1, 2").unwrap_err();
+    assert_eq!((err.span.start.line, err.span.start.column, err.found), (2, 10, None));
+    assert!(err.expected.contains(&"')'".to_string()));
+    let err = p.parse_ast("1 + 2 = 3").unwrap_err();
+    assert_eq!(err.span.start.column, 7);
+    assert_eq!(err.expected, vec!["operator", "end of input"]);
+    let err = p.parse_ast("1 < 2 3").unwrap_err();
+    assert_eq!(err.expected, vec!["operator", "end of input"]);
+    let err = p.parse_ast("").unwrap_err();
+    assert_eq!(err.to_string(), "expected unary operator, name, number, string, '[', '{' or '(' at 1:1, found end of input");
 }
 
 }
@@ -18,12 +18,14 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::basic_parser::State;
-use crate::basic_parser::Transition;
+use crate::basic_parser;
+use crate::basic_parser::{
+    alt, chr, chr_range, many0, many1, unicode_alpha, unicode_alphanum_str, unicode_letter, Parser, State,
+};
 
 #[test]
 fn chr_match() {
-    let matcher = chr!('a');
+    let matcher = chr('a');
     let result = matcher.matches(&State::from_string("abcd"));
     assert!(result.is_some());
     assert_eq!(result.unwrap().peek_many(3), "bcd");
@@ -31,14 +33,14 @@ fn chr_match() {
 
 #[test]
 fn chr_no_match() {
-    let matcher = chr!('a');
+    let matcher = chr('a');
     let result = matcher.matches(&State::from_string("ABCDabcd"));
     assert!(result.is_none());
 }
 
 #[test]
 fn range_chr_match() {
-    let matcher = chr!('0', '9');
+    let matcher = chr_range('0', '9');
     let result = matcher.matches(&State::from_string("0"));
     assert!(result.is_some());
     let result = matcher.matches(&State::from_string("5"));
@@ -49,14 +51,14 @@ fn range_chr_match() {
 
 #[test]
 fn range_chr_no_match() {
-    let matcher = chr!('0', '9');
+    let matcher = chr_range('0', '9');
     let result = matcher.matches(&State::from_string("ABCD1234"));
     assert!(result.is_none());
 }
 
 #[test]
 fn seq() {
-    let matcher = seq!(chr!('a'), chr!('b'), chr!('c'));
+    let matcher = basic_parser::seq(chr('a'), basic_parser::seq(chr('b'), chr('c')));
     let result = matcher.matches(&State::from_string("abcd"));
     assert!(result.is_some());
     assert_eq!(result.unwrap().peek(), 'd');
@@ -65,8 +67,8 @@ fn seq() {
 #[test]
 fn alt_match() {
     let state = State::from_string("abc");
-    let matcher1 = alt!(chr!('a'), chr!('b'));
-    let matcher2 = alt!(chr!('b'), chr!('a'));
+    let matcher1 = alt(chr('a'), chr('b'));
+    let matcher2 = alt(chr('b'), chr('a'));
     let result1 = matcher1.matches(&state);
     assert!(result1.is_some());
     assert_eq!(result1.unwrap().peek(), 'b');
@@ -78,14 +80,14 @@ fn alt_match() {
 #[test]
 fn alt_no_match() {
     let state = State::from_string("cba");
-    let matcher = alt!(chr!('a'), chr!('b'));
+    let matcher = alt(chr('a'), chr('b'));
     let result = matcher.matches(&state);
     assert!(result.is_none());
 }
 
 #[test]
 fn opt() {
-    let matcher = opt!(chr!('b'));
+    let matcher = basic_parser::opt(chr('b'));
     // For opt being false
     let result= matcher.matches(&State::from_string("abc"));
     assert!(result.is_some());
@@ -99,20 +101,20 @@ fn opt() {
 #[test]
 fn rep() {
     // Testing ?
-    let matcher = rep!(chr!('a'), '?');
+    let matcher = basic_parser::opt(chr('a'));
     let mut result = matcher.matches(&State::from_string("abcd"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("bcda"));
     assert!(result.is_some());
     // Testing +
-    let matcher = rep!(chr!('a'), '+');
+    let matcher = many1(chr('a'));
     result = matcher.matches(&State::from_string("aabcd"));
     assert!(result.is_some());
     assert_eq!(result.unwrap().peek(), 'b');
     result = matcher.matches(&State::from_string("bcda"));
     assert!(result.is_none());
     // Testing *
-    let matcher = rep!(chr!('a'), '*');
+    let matcher = many0(chr('a'));
     result = matcher.matches(&State::from_string("aaaaaaabcd"));
     assert!(result.is_some());
     assert_eq!(result.unwrap().peek(), 'b');
@@ -123,7 +125,7 @@ fn rep() {
 
 #[test]
 fn lower_letter() {
-    let matcher = lower_letter!();
+    let matcher = basic_parser::lower_letter();
     let mut result = matcher.matches(&State::from_string("a"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("z"));
@@ -134,7 +136,7 @@ fn lower_letter() {
 
 #[test]
 fn upper_letter() {
-    let matcher = upper_letter!();
+    let matcher = basic_parser::upper_letter();
     let mut result = matcher.matches(&State::from_string("A"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("Z"));
@@ -145,7 +147,7 @@ fn upper_letter() {
 
 #[test]
 fn letter() {
-    let matcher = letter!();
+    let matcher = basic_parser::letter();
     let mut result = matcher.matches(&State::from_string("A"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("Z"));
@@ -162,7 +164,7 @@ fn letter() {
 
 #[test]
 fn alpha() {
-    let matcher = alpha!();
+    let matcher = basic_parser::alpha();
     let mut result = matcher.matches(&State::from_string("A"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("z"));
@@ -175,7 +177,7 @@ fn alpha() {
 
 #[test]
 fn digit() {
-    let matcher = digit!();
+    let matcher = basic_parser::digit();
     let mut result = matcher.matches(&State::from_string("0"));
     assert!(result.is_some());
     result = matcher.matches(&State::from_string("9"));
@@ -186,7 +188,7 @@ fn digit() {
 
 #[test]
 fn letter_str() {
-    let matcher = letter_str!();
+    let matcher = basic_parser::letter_str();
     let mut result = matcher.matches(&State::from_string("Hello"));
     assert!(result.is_some());
     assert!(result.unwrap().complete());
@@ -200,7 +202,7 @@ fn letter_str() {
 
 #[test]
 fn alpha_str() {
-    let matcher = alpha_str!();
+    let matcher = basic_parser::alpha_str();
     let mut result = matcher.matches(&State::from_string("Hello"));
     assert!(result.is_some());
     assert!(result.unwrap().complete());
@@ -214,7 +216,7 @@ fn alpha_str() {
 
 #[test]
 fn alphanum_str() {
-    let matcher = alphanum_str!();
+    let matcher = basic_parser::alphanum_str();
     let mut result = matcher.matches(&State::from_string("Hello"));
     assert!(result.is_some());
     assert!(result.unwrap().complete());
@@ -225,3 +227,48 @@ fn alphanum_str() {
     assert!(result.is_some());
     assert!(result.unwrap().complete());
 }
+
+#[test]
+fn chr_unicode() {
+    let matcher = chr_range('a', 'z');
+    let result = matcher.matches(&State::from_string("é"));
+    assert!(result.is_none());
+    let matcher = chr('é');
+    let result = matcher.matches(&State::from_string("éa"));
+    assert_eq!(result.unwrap().peek(), 'a');
+}
+
+#[test]
+fn letter_unicode() {
+    let matcher = unicode_letter();
+    let mut result = matcher.matches(&State::from_string("a"));
+    assert!(result.is_some());
+    result = matcher.matches(&State::from_string("ß"));
+    assert!(result.is_some());
+    result = matcher.matches(&State::from_string("_"));
+    assert!(result.is_none());
+    result = matcher.matches(&State::from_string("٣"));
+    assert!(result.is_none());
+    result = matcher.matches(&State::from_string(""));
+    assert!(result.is_none());
+}
+
+#[test]
+fn alpha_unicode() {
+    let matcher = unicode_alpha();
+    let mut result = matcher.matches(&State::from_string("Ж"));
+    assert!(result.is_some());
+    result = matcher.matches(&State::from_string("_"));
+    assert!(result.is_some());
+    result = matcher.matches(&State::from_string("€"));
+    assert!(result.is_none());
+}
+
+#[test]
+fn alphanum_str_unicode() {
+    let matcher = unicode_alphanum_str();
+    let result = matcher.matches(&State::from_string("naïve_٣2"));
+    assert!(result.unwrap().complete());
+    let result = matcher.matches(&State::from_string("año-1"));
+    assert_eq!(result.unwrap().peek(), '-');
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2019 Marco Giglio
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::basic_parser::{chr, chr_if, chr_range, digit, many1, Combinator, Parser, State, Transition};
+
+#[test]
+fn recognize_transition() {
+    let number = many1(digit()).recognize();
+    let (value, state) = number.parse(&State::from_string("123abc")).unwrap();
+    assert_eq!(value, "123");
+    assert_eq!(state.peek(), 'a');
+    assert!(number.parse(&State::from_string("abc")).is_none());
+}
+
+#[test]
+fn map_values() {
+    let number = many1(digit()).recognize().map(|s: &str| s.len());
+    let (value, state) = number.parse(&State::from_string("1234")).unwrap();
+    assert_eq!(value, 4);
+    assert!(state.complete());
+}
+
+#[test]
+fn and_then_values() {
+    let byte = many1(digit()).recognize().and_then(|s: &str| s.parse::<u8>().ok());
+    let (value, _) = byte.parse(&State::from_string("255")).unwrap();
+    assert_eq!(value, 255);
+    // The conversion failing fails the match
+    assert!(byte.parse(&State::from_string("256")).is_none());
+}
+
+#[test]
+fn many0_values() {
+    let letters = chr_range('a', 'z').recognize().many0();
+    let (value, state) = letters.parse(&State::from_string("abc1")).unwrap();
+    assert_eq!(value, vec!["a", "b", "c"]);
+    assert_eq!(state.peek(), '1');
+    let (value, state) = letters.parse(&State::from_string("1")).unwrap();
+    assert!(value.is_empty());
+    assert_eq!(state.peek(), '1');
+    // An empty match does not loop forever
+    let empty = Combinator::new(|state| Some(((), *state))).many0();
+    let (value, _) = empty.parse(&State::from_string("abc")).unwrap();
+    assert!(value.is_empty());
+}
+
+#[test]
+fn sep_by_values() {
+    let numbers = many1(digit())
+        .recognize()
+        .and_then(|s: &str| s.parse::<i64>().ok())
+        .sep_by(chr(','));
+    let (value, state) = numbers.parse(&State::from_string("1,22,333")).unwrap();
+    assert_eq!(value, vec![1, 22, 333]);
+    assert!(state.complete());
+    // A trailing separator is not consumed
+    let (value, state) = numbers.parse(&State::from_string("1,2,")).unwrap();
+    assert_eq!(value, vec![1, 2]);
+    assert_eq!(state.display(), ",");
+    let (value, state) = numbers.parse(&State::from_string("x")).unwrap();
+    assert!(value.is_empty());
+    assert_eq!(state.peek(), 'x');
+}
+
+#[test]
+fn recognize_combinator() {
+    let list = chr_range('a', 'z').recognize().sep_by(chr(',')).recognize();
+    let (value, state) = list.parse(&State::from_string("a,b,c;")).unwrap();
+    assert_eq!(value, "a,b,c");
+    assert_eq!(state.peek(), ';');
+}
+
+#[test]
+fn then_values() {
+    let pair = chr('a').then(digit()).then(chr('b'));
+    let ((first, second), third) = pair.parse(&State::from_string("a1b")).unwrap().0;
+    assert_eq!((first, second, third), ('a', '1', 'b'));
+    assert!(pair.parse(&State::from_string("a1c")).is_none());
+}
+
+#[test]
+fn or_values() {
+    let sign = chr('+').or(chr('-')).map(|c| if c == '-' { -1 } else { 1 });
+    assert_eq!(sign.parse(&State::from_string("-")).unwrap().0, -1);
+    assert_eq!(sign.parse(&State::from_string("+")).unwrap().0, 1);
+    assert!(sign.parse(&State::from_string("*")).is_none());
+}
+
+#[test]
+fn opt_values() {
+    let sign = chr('-').opt();
+    let (value, state) = sign.parse(&State::from_string("-1")).unwrap();
+    assert_eq!(value, Some('-'));
+    assert_eq!(state.peek(), '1');
+    let (value, state) = sign.parse(&State::from_string("1")).unwrap();
+    assert_eq!(value, None);
+    assert_eq!(state.peek(), '1');
+}
+
+#[test]
+fn many1_values() {
+    let digits = digit().many1();
+    let (value, state) = digits.parse(&State::from_string("42x")).unwrap();
+    assert_eq!(value, vec!['4', '2']);
+    assert_eq!(state.peek(), 'x');
+    assert!(digits.parse(&State::from_string("x")).is_none());
+}
+
+#[test]
+fn transition() {
+    let ab = Transition::new(|state| Some(state.read(2)).filter(|_| state.peek_many(2) == "ab"));
+    let (value, state) = ab.many0().recognize().parse(&State::from_string("ababc")).unwrap();
+    assert_eq!(value, "abab");
+    assert_eq!(state.peek(), 'c');
+}
+
+#[test]
+fn reused_parser() {
+    let number = digit().many1().recognize();
+    let pair = (&number).then(chr(',')).then(&number).map(|((a, _), b)| (a, b));
+    let (value, _) = pair.parse(&State::from_string("12,345")).unwrap();
+    assert_eq!(value, ("12", "345"));
+    assert_eq!(number.parse(&State::from_string("7")).unwrap().0, "7");
+}
+
+#[test]
+fn grammar() {
+    // A list of integers, such as "[1, -2, 3]"
+    let ws = || chr(' ').many0();
+    let integer = chr('-').opt().then(digit().many1())
+        .recognize()
+        .and_then(|s: &str| s.parse::<i64>().ok());
+    let list = chr('[')
+        .then(integer.sep_by(chr(',').then(ws())))
+        .then(chr(']'))
+        .map(|((_, items), _)| items);
+    let (value, state) = list.parse(&State::from_string("[1, -2,3]")).unwrap();
+    assert_eq!(value, vec![1, -2, 3]);
+    assert!(state.complete());
+    assert!(list.parse(&State::from_string("[1, ]")).is_none());
+}
+
+#[test]
+fn run() {
+    let number = digit().many1().recognize();
+    assert_eq!(number.run("42").unwrap(), "42");
+    let err = number.run("42x").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit or end of input at 1:3, found 'x'");
+    let err = number.run("").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit at 1:1, found end of input");
+}
+
+#[test]
+fn merged_alternatives() {
+    let sign = chr('+').or(chr('-')).or(chr_range('a', 'f'));
+    let err = sign.run("*").unwrap_err();
+    assert_eq!(err.expected, vec!["'+'", "'-'", "'a'..'f'"]);
+    assert_eq!(err.to_string(), "expected '+', '-' or 'a'..'f' at 1:1, found '*'");
+    // The same label is only reported once
+    let err = chr('a').or(chr('a')).run("b").unwrap_err();
+    assert_eq!(err.expected, vec!["'a'"]);
+}
+
+#[test]
+fn furthest_failure() {
+    // The failing item past the separator goes further than the closing
+    // bracket, so it is the one reported
+    let list = chr('[')
+        .then(digit().many1().sep_by(chr(',')))
+        .then(chr(']'));
+    let err = list.run("[1,2,x]").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit at 1:6, found 'x'");
+    // Where repetitions stop, what could have continued them is reported
+    let err = list.run("[12;").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit, ',' or ']' at 1:4, found ';'");
+}
+
+#[test]
+fn label() {
+    let number = || digit().many1().recognize().label("number");
+    let value = number().or(chr('(').then(chr(')')).recognize()).label("value");
+    let err = value.run("x").unwrap_err();
+    assert_eq!(err.to_string(), "expected value at 1:1, found 'x'");
+    // A failure past the start of the rule is more precise than its name
+    let err = value.run("(x").unwrap_err();
+    assert_eq!(err.to_string(), "expected ')' at 1:2, found 'x'");
+    // Labels of other rules failing at the same position are kept
+    let err = chr('-').recognize().or(number()).run("x").unwrap_err();
+    assert_eq!(err.expected, vec!["'-'", "number"]);
+    let err = chr('-').opt().label("sign").then(number()).run("x").unwrap_err();
+    assert_eq!(err.expected, vec!["sign", "number"]);
+}
+
+#[test]
+fn hidden() {
+    let spaces = chr(' ').many0().hidden();
+    let err = (&spaces).then(digit()).run(" x").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit at 1:2, found 'x'");
+    let err = digit().then(spaces).then(chr(';')).run("1x").unwrap_err();
+    assert_eq!(err.to_string(), "expected ';' at 1:2, found 'x'");
+}
+
+#[test]
+fn unlabeled_failure() {
+    let vowel = chr_if(|c| "aeiou".contains(c));
+    assert_eq!(vowel.run("x").unwrap_err().to_string(), "unexpected 'x' at 1:1");
+    assert_eq!(vowel.run("").unwrap_err().to_string(), "unexpected end of input at 1:1");
+    let byte = digit().many1().recognize().and_then(|s: &str| s.parse::<u8>().ok());
+    let err = (&byte).label("byte").run("256").unwrap_err();
+    assert_eq!(err.to_string(), "expected byte at 1:1, found '2'");
+    // Without a label, the rejected value is still what is reported
+    let err = byte.run("256").unwrap_err();
+    assert_eq!(err.to_string(), "unexpected '2' at 1:1");
+    // Failures of the accepted value are kept
+    let err = byte.then(chr(';')).run("25x").unwrap_err();
+    assert_eq!(err.to_string(), "expected digit or ';' at 1:3, found 'x'");
+    let err = vowel.label("vowel").run("x").unwrap_err();
+    assert_eq!(err.to_string(), "expected vowel at 1:1, found 'x'");
+}
@@ -0,0 +1,537 @@
+// Copyright (c) 2019 Marco Giglio
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::interpreter::{ASTNode, Context, EvalError, SigilPolicy, Value};
+use crate::parser::{precedence, Associativity, Operator, Parser};
+
+fn eval(s: &str, ctx: &Context) -> Result<Value, EvalError> {
+    match Parser::new().parse_ast(s) {
+        Ok(node) => node.interpret(ctx),
+        Err(err) => panic!("{}: {}", s, err),
+    }
+}
+
+fn context() -> Context {
+    let mut ctx = Context::new();
+    ctx.set("x", 7);
+    ctx.set("a.b", 3);
+    ctx.set("flag", true);
+    ctx.set("max", i64::MAX);
+    ctx
+}
+
+#[test]
+fn arithmetic() {
+    let ctx = context();
+    assert_eq!(eval("1 + 2 * 3", &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval("(1 + 2) * 3", &ctx), Ok(Value::Integer(9)));
+    assert_eq!(eval("10 - 4 - 3", &ctx), Ok(Value::Integer(3)));
+    assert_eq!(eval("17 / 5 % 2", &ctx), Ok(Value::Integer(1)));
+    assert_eq!(eval("$a.b + x", &ctx), Ok(Value::Integer(10)));
+    assert_eq!(eval("0 - x / 2", &ctx), Ok(Value::Integer(-3)));
+    assert_eq!(eval("$flag", &ctx), Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn arithmetic_errors() {
+    let ctx = context();
+    assert!(matches!(eval("1 / (x - 7)", &ctx), Err(EvalError::DivisionByZero { .. })));
+    assert!(matches!(eval("1 % 0", &ctx), Err(EvalError::DivisionByZero { .. })));
+    assert!(matches!(eval("max + 1", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("max * 2", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("y + 1", &ctx), Err(EvalError::UnknownVariable { .. })));
+    assert!(matches!(eval("f(1) + 1", &ctx), Err(EvalError::UnknownFunction { .. })));
+    let err = eval("1 + flag", &ctx).unwrap_err();
+    assert_eq!(err, EvalError::TypeMismatch { expected: "number", found: "boolean", span: err.span() });
+    assert_eq!((err.span().start.offset, err.span().end.offset), (4, 8));
+    assert_eq!(err.to_string(), "expected number, found boolean at 1:5");
+}
+
+#[test]
+fn float_arithmetic() {
+    let ctx = context();
+    assert_eq!(eval("3.5 * 2", &ctx), Ok(Value::Float(7.0)));
+    assert_eq!(eval("1 + .5", &ctx), Ok(Value::Float(1.5)));
+    assert_eq!(eval("x / 2.0", &ctx), Ok(Value::Float(3.5)));
+    assert_eq!(eval("x / 2", &ctx), Ok(Value::Integer(3)));
+    assert_eq!(eval("7.5 % 2", &ctx), Ok(Value::Float(1.5)));
+    assert_eq!(eval("1e-3 - 1e-3", &ctx), Ok(Value::Float(0.0)));
+    assert_eq!(eval("2.5", &ctx).unwrap().to_string(), "2.5");
+    assert_eq!(eval("2.0", &ctx).unwrap().to_string(), "2.0");
+    assert!(matches!(eval("1.5 / 0", &ctx), Err(EvalError::DivisionByZero { .. })));
+    assert!(matches!(eval("1 % 0.0", &ctx), Err(EvalError::DivisionByZero { .. })));
+    assert!(matches!(eval("1e300 * 1e300", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("1.5 + flag", &ctx), Err(EvalError::TypeMismatch { expected: "number", .. })));
+}
+
+#[test]
+fn float_comparison() {
+    let mut ctx = context();
+    ctx.set("ratio", 0.25);
+    assert_eq!(eval("1 == 1.0", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("1 != 1.0", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("$ratio < 0.5", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$ratio * 4 >= 1", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x > 6.9", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("0.1 + 0.2 > 0.3", &ctx), Ok(Value::Boolean(true)));
+    // Integers are compared exactly
+    assert_eq!(eval("max - 1 < max", &ctx), Ok(Value::Boolean(true)));
+    assert!(matches!(eval("flag == 1.0", &ctx), Err(EvalError::TypeMismatch { expected: "boolean", found: "float", .. })));
+}
+
+#[test]
+fn comparison() {
+    let ctx = context();
+    assert_eq!(eval("x > 3", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x >= 7", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x < 7", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("x <= 6", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("x == 7", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x != 7", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("flag == flag", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("flag != flag", &ctx), Ok(Value::Boolean(false)));
+    assert!(matches!(eval("flag == 1", &ctx), Err(EvalError::TypeMismatch { expected: "boolean", found: "integer", .. })));
+    assert!(matches!(eval("flag < 1", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "boolean", .. })));
+}
+
+#[test]
+fn logic() {
+    let ctx = context();
+    assert_eq!(eval("2 < 3 && 5 < 4", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("2 > 3 || 5 > 4", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("1 == 2 || 2 == 2 && 3 == 4", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("!(x == 7)", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("!(x == 7) || !(1 > 2)", &ctx), Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn logic_short_circuit() {
+    let ctx = context();
+    assert_eq!(eval("x < 8 || y > 0", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x > 8 && 1 / 0 > 0", &ctx), Ok(Value::Boolean(false)));
+    assert!(matches!(eval("x > 8 || y > 0", &ctx), Err(EvalError::UnknownVariable { .. })));
+}
+
+#[test]
+fn function_call() {
+    let mut ctx = context();
+    ctx.register("max", 2, |args| match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(*a.max(b))),
+        _ => Err("integers expected".to_string()),
+    });
+    ctx.register("math.answer", 0, |_| Ok(Value::Integer(42)));
+    ctx.register("is.odd", 1, |args| match args[0] {
+        Value::Integer(v) => Ok(Value::Boolean(v % 2 != 0)),
+        _ => Err("integer expected".to_string()),
+    });
+    assert_eq!(eval("max(x, 3) + max(1, 2)", &ctx), Ok(Value::Integer(9)));
+    assert_eq!(eval("$math.answer()", &ctx), Ok(Value::Integer(42)));
+    assert_eq!(eval("max(max(1, 5), math.answer())", &ctx), Ok(Value::Integer(42)));
+    assert_eq!(eval("is.odd(x) == is.odd(1)", &ctx), Ok(Value::Boolean(true)));
+}
+
+#[test]
+fn function_call_errors() {
+    let mut ctx = context();
+    ctx.register("max", 2, |args| match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(*a.max(b))),
+        _ => Err("integers expected".to_string()),
+    });
+    let err = eval("min(1, 2)", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "unknown function min at 1:1");
+    let err = eval("1 + max(1)", &ctx).unwrap_err();
+    assert!(matches!(err, EvalError::WrongArity { expected: 2, found: 1, .. }));
+    assert_eq!(err.to_string(), "max takes 2 argument(s) but 1 were given at 1:5");
+    assert!(matches!(eval("max(1, y)", &ctx), Err(EvalError::UnknownVariable { .. })));
+    let err = eval("max(1, flag)", &ctx).unwrap_err();
+    assert!(matches!(&err, EvalError::FunctionFailed { name, message, .. }
+                     if name == "max" && message == "integers expected"));
+    assert_eq!(err.to_string(), "max failed: integers expected at 1:1");
+}
+
+#[test]
+fn nested_variables() {
+    let mut ctx = Context::new();
+    ctx.set("user.address.city", 7);
+    ctx.set("user.address.zip", 20100);
+    ctx.set("user.age", 42);
+    let mut limits = BTreeMap::new();
+    limits.insert("age".to_string(), Value::Integer(18));
+    ctx.set("limits", limits);
+    assert_eq!(eval("$user.address.city", &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval("$user.age >= $limits.age", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(ctx.get("user.address.zip"), Some(&Value::Integer(20100)));
+    assert_eq!(ctx.get("user.address.country"), None);
+    assert_eq!(ctx.get("user").unwrap().to_string(), "{address: {city: 7, zip: 20100}, age: 42}");
+    // Overwriting a leaf with a nested value replaces it
+    ctx.set("user.age.years", 42);
+    assert_eq!(eval("user.age.years", &ctx), Ok(Value::Integer(42)));
+}
+
+#[test]
+fn nested_variables_errors() {
+    let mut ctx = Context::new();
+    ctx.set("user.address.city", 7);
+    let err = eval("$person.address", &ctx).unwrap_err();
+    assert!(matches!(&err, EvalError::UnknownVariable { name, .. } if name == "$person"));
+    assert_eq!(err.to_string(), "unknown variable $person at 1:1");
+    let err = eval("$user.address.street", &ctx).unwrap_err();
+    assert!(matches!(&err, EvalError::MissingField { path, field, .. }
+                     if path == "$user.address" && field == "street"));
+    assert_eq!(err.to_string(), "missing field street on $user.address at 1:1");
+    let err = eval("$user.address.city.name", &ctx).unwrap_err();
+    assert!(matches!(err, EvalError::TypeMismatch { expected: "map", found: "integer", .. }));
+    let err = eval("$user.address + 1", &ctx).unwrap_err();
+    assert!(matches!(err, EvalError::TypeMismatch { expected: "number", found: "map", .. }));
+}
+
+#[test]
+fn sigil_lenient() {
+    let mut ctx = Context::new();
+    ctx.set("x", 1);
+    ctx.set("y", 2);
+    ctx.set_local("x", 10);
+    ctx.set_local("z", 30);
+    ctx.register("f", 0, |_| Ok(Value::Integer(1)));
+    ctx.register_builtin("f", 0, |_| Ok(Value::Integer(10)));
+    ctx.register_builtin("g", 0, |_| Ok(Value::Integer(20)));
+    assert_eq!(eval("$x", &ctx), Ok(Value::Integer(1)));
+    assert_eq!(eval("x", &ctx), Ok(Value::Integer(10)));
+    assert_eq!(eval("y", &ctx), Ok(Value::Integer(2)));
+    assert_eq!(eval("z", &ctx), Ok(Value::Integer(30)));
+    assert_eq!(eval("$f()", &ctx), Ok(Value::Integer(1)));
+    assert_eq!(eval("f()", &ctx), Ok(Value::Integer(10)));
+    assert_eq!(eval("g()", &ctx), Ok(Value::Integer(20)));
+    // Host data can not be shadowed by locals
+    let err = eval("$z", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "unknown variable $z at 1:1");
+    let err = eval("$g()", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "unknown function $g at 1:1");
+}
+
+#[test]
+fn sigil_strict() {
+    let mut ctx = Context::new().sigil_policy(SigilPolicy::Strict);
+    ctx.set("x", 1);
+    ctx.set("y", 2);
+    ctx.set_local("x", 10);
+    ctx.register("f", 0, |_| Ok(Value::Integer(1)));
+    assert_eq!(eval("$x + x", &ctx), Ok(Value::Integer(11)));
+    assert_eq!(eval("$y", &ctx), Ok(Value::Integer(2)));
+    let err = eval("y", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "unknown variable y at 1:1");
+    assert_eq!(eval("$f()", &ctx), Ok(Value::Integer(1)));
+    let err = eval("f()", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "unknown function f at 1:1");
+}
+
+#[test]
+fn string_comparison() {
+    let mut ctx = context();
+    ctx.set("user.role", "admin");
+    ctx.set("user.name", "Zoë");
+    assert_eq!(eval("'it\\'s'", &ctx), Ok(Value::String("it's".to_string())));
+    assert_eq!(eval(r#"$user.role == "admin""#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$user.role != 'admin'", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("$user.role < 'root'", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'b' >= 'abc'", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'' <= ''", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$user.name > 'Zoe'", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'caf\\u{e9}' == 'café'", &ctx), Ok(Value::Boolean(true)));
+    assert!(matches!(eval("$user.role == 1", &ctx), Err(EvalError::TypeMismatch { expected: "string", found: "integer", .. })));
+    assert!(matches!(eval("$user.role < 1", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "string", .. })));
+    assert!(matches!(eval("'a' + 'b'", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "string", .. })));
+    assert_eq!(ctx.get("user").unwrap().to_string(), r#"{name: "Zoë", role: "admin"}"#);
+}
+
+#[test]
+fn booleans() {
+    let mut ctx = context();
+    ctx.set("off", false);
+    assert_eq!(eval("true", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$flag == true", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$off != false", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("$flag && !$off", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$off || x > 5", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("!(false || $off)", &ctx), Ok(Value::Boolean(true)));
+    assert!(matches!(eval("$flag == 1", &ctx), Err(EvalError::TypeMismatch { expected: "boolean", found: "integer", .. })));
+    assert!(matches!(eval("true < false", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "boolean", .. })));
+    assert!(matches!(eval("true + 1", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "boolean", .. })));
+    let err = Parser::new().parse("$x && $flag", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "expected boolean, found integer at 1:1");
+}
+
+#[test]
+fn null() {
+    let mut ctx = context();
+    ctx.set("nothing", Value::Null);
+    assert_eq!(eval("null == null", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$nothing == null", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$x == null", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("null != 'null'", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$a != null && $a.b == 3", &ctx), Ok(Value::Boolean(true)));
+    assert!(matches!(eval("$nothing < 1", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "null", .. })));
+    assert!(matches!(eval("null", &ctx), Ok(Value::Null)));
+    assert!(matches!(eval("!null", &ctx), Err(EvalError::TypeMismatch { expected: "boolean", found: "null", .. })));
+    assert_eq!(Value::Null.to_string(), "null");
+}
+
+#[test]
+fn unary() {
+    let ctx = context();
+    assert_eq!(eval("-5", &ctx), Ok(Value::Integer(-5)));
+    assert_eq!(eval("-$x * 2", &ctx), Ok(Value::Integer(-14)));
+    assert_eq!(eval("3 - -1", &ctx), Ok(Value::Integer(4)));
+    assert_eq!(eval("- -x", &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval("+x - +1", &ctx), Ok(Value::Integer(6)));
+    assert_eq!(eval("-(1 + 2) * 2", &ctx), Ok(Value::Integer(-6)));
+    assert_eq!(eval("-2.5 + 1", &ctx), Ok(Value::Float(-1.5)));
+    assert_eq!(eval("-max - 1", &ctx), Ok(Value::Integer(i64::MIN)));
+    assert_eq!(eval("$x > -5", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("-0.5 < -$a.b", &ctx), Ok(Value::Boolean(false)));
+    assert!(matches!(eval("-(-max - 1)", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("-flag", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "boolean", .. })));
+    assert!(matches!(eval("+'a'", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "string", .. })));
+}
+
+#[test]
+fn ternary() {
+    let ctx = context();
+    assert_eq!(eval("$flag ? 10 : 5", &ctx), Ok(Value::Integer(10)));
+    assert_eq!(eval("$x > 10 ? 'big' : 'small'", &ctx), Ok(Value::String("small".to_string())));
+    assert_eq!(eval("($x > 5 ? $a.b : 0) * 2 == 6", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("!flag ? 1 : (x < 0 ? 2 : 3)", &ctx), Ok(Value::Integer(3)));
+    // The branch that is not chosen is never evaluated
+    assert_eq!(eval("flag ? 1 : unknown(x) / 0", &ctx), Ok(Value::Integer(1)));
+    assert_eq!(eval("!flag ? $missing : x % 2", &ctx), Ok(Value::Integer(1)));
+    assert!(matches!(eval("x ? 1 : 2", &ctx), Err(EvalError::TypeMismatch { expected: "boolean", found: "integer", .. })));
+    assert!(matches!(eval("flag ? 1 / 0 : 2", &ctx), Err(EvalError::DivisionByZero { .. })));
+}
+
+#[test]
+fn function_call_expressions() {
+    let mut ctx = context();
+    ctx.register("max", 2, |args| match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(*a.max(b))),
+        _ => Err("expected integers".to_string()),
+    });
+    ctx.register("not", 1, |args| match &args[0] {
+        Value::Boolean(b) => Ok(Value::Boolean(!b)),
+        _ => Err("expected a boolean".to_string()),
+    });
+    assert_eq!(eval("max($x + 1, 2 * $a.b)", &ctx), Ok(Value::Integer(8)));
+    assert_eq!(eval("max( max(1, -x) , (x - 10) * -1 ) == 3", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("not(x > 3)", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("not(flag && x < 3 || false)", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("max(flag ? 1 : 2, 0)", &ctx), Ok(Value::Integer(1)));
+    assert!(matches!(eval("max(x / 0, 1)", &ctx), Err(EvalError::DivisionByZero { .. })));
+    let err = eval("max(1, x > 3)", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "max failed: expected integers at 1:1");
+}
+
+#[test]
+fn mixed_expressions() {
+    let ctx = context();
+    assert_eq!(eval("(x > 1) == (a.b > 2)", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("(x > 1) != flag", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("!x > 10 && flag", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("-x * 2 + 20 > 5 ? 'yes' : 'no'", &ctx), Ok(Value::String("yes".to_string())));
+    assert!(matches!(eval("1 < 2 < 3", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "boolean", .. })));
+}
+
+#[test]
+fn custom_operators() {
+    let mut ctx = context();
+    ctx.register("pow", 2, |args| match (&args[0], &args[1]) {
+        (Value::Integer(b), Value::Integer(e)) if *e >= 0 => Ok(Value::Integer(b.pow(*e as u32))),
+        _ => Err("negative exponent".to_string()),
+    });
+    ctx.register("fact", 1, |args| match &args[0] {
+        Value::Integer(n) => Ok(Value::Integer((1..=*n).product())),
+        _ => Err("expected an integer".to_string()),
+    });
+    ctx.register("div", 2, |args| match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) if *b != 0 => Ok(Value::Integer(a.div_euclid(*b))),
+        _ => Err("invalid operands".to_string()),
+    });
+    let p = Parser::new()
+        .operator(Operator::infix("**", precedence::UNARY + 10, Associativity::Right, "pow"))
+        .operator(Operator::postfix("!", precedence::UNARY + 10, "fact"))
+        .operator(Operator::infix("div", precedence::PRODUCT, Associativity::Left, "div"))
+        .operator(Operator::prefix("~~", precedence::UNARY, "missing"));
+    let eval = |s| p.parse_ast(s).unwrap().interpret(&ctx);
+    assert_eq!(eval("2 ** 3 ** 2"), Ok(Value::Integer(512)));
+    assert_eq!(eval("-2 ** 2"), Ok(Value::Integer(-4)));
+    assert_eq!(eval("3! + 1"), Ok(Value::Integer(7)));
+    assert_eq!(eval("a.b! != 6"), Ok(Value::Boolean(false)));
+    assert_eq!(eval("-7 div 2 * 3"), Ok(Value::Integer(-12)));
+    assert_eq!(eval("!(x div 2 == 3)"), Ok(Value::Boolean(false)));
+    let err = eval("2 ** -1").unwrap_err();
+    assert_eq!(err.to_string(), "pow failed: negative exponent at 1:1");
+    assert!(matches!(eval("~~x"), Err(EvalError::UnknownFunction { ref name, .. }) if name == "missing"));
+    assert!(matches!(eval("2 ** (1 / 0)"), Err(EvalError::DivisionByZero { .. })));
+}
+
+#[test]
+fn lists() {
+    let mut ctx = context();
+    ctx.set("items", vec![10, 20, 30]);
+    ctx.set("matrix", vec![vec![1, 2], vec![3, 4]]);
+    ctx.set("user.tags", vec!["admin", "dev"]);
+    assert_eq!(eval("$items[0]", &ctx), Ok(Value::Integer(10)));
+    assert_eq!(eval("$matrix[1][0] + $matrix[0][1]", &ctx), Ok(Value::Integer(5)));
+    assert_eq!(eval("$user.tags[$a.b - 2] == 'dev'", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("[1, x, 'a'][1]", &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval("[1, x + 1, [flag]]", &ctx), Ok(Value::from(vec![Value::from(1), Value::from(8), Value::from(vec![true])])));
+    assert_eq!(eval("[] == []", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("[1, [2]] == [1.0, [2.0]]", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("[1] != [1.0]", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("[1] == [1, 2]", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("[1] == ['1']", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("$items != [10, 20]", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("[1, 'a']", &ctx).unwrap().to_string(), r#"[1, "a"]"#);
+    let err = eval("$items[3]", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "index 3 out of bounds for a list of length 3 at 1:8");
+    assert!(matches!(eval("$items[-1]", &ctx), Err(EvalError::IndexOutOfBounds { index: -1, len: 3, .. })));
+    assert!(matches!(eval("$matrix[0][2]", &ctx), Err(EvalError::IndexOutOfBounds { index: 2, len: 2, .. })));
+    assert!(matches!(eval("$items[1.0]", &ctx), Err(EvalError::TypeMismatch { expected: "integer", found: "float", .. })));
+    assert!(matches!(eval("$x[0]", &ctx), Err(EvalError::TypeMismatch { expected: "list", found: "integer", .. })));
+    assert!(matches!(eval("$items['a']", &ctx), Err(EvalError::TypeMismatch { expected: "integer", found: "string", .. })));
+    assert!(matches!(eval("[1][x / 0]", &ctx), Err(EvalError::DivisionByZero { .. })));
+    assert!(matches!(eval("$items < [1]", &ctx), Err(EvalError::TypeMismatch { expected: "number", found: "list", .. })));
+}
+
+#[test]
+fn maps() {
+    let mut ctx = context();
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "json");
+    headers.insert("2fa".to_string(), "on");
+    ctx.set("headers", headers);
+    ctx.set("user.address.city", "Rome");
+    assert_eq!(eval(r#"$headers["Content-Type"] == "json""#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$headers['2fa']", &ctx), Ok(Value::from("on")));
+    // Both access styles resolve the same way
+    assert_eq!(eval("$user['address']['city']", &ctx), eval("$user.address.city", &ctx));
+    assert_eq!(eval("$user.address['city']", &ctx), Ok(Value::from("Rome")));
+    assert_eq!(eval("$a['b'] == $a.b", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval(r#"{"a": 1, "b": $x}["b"]"#, &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval(r#"{"a": 1, "a": 2}"#, &ctx).unwrap().to_string(), "{a: 2}");
+    assert_eq!(eval(r#"{"k": [1, {"n": x}]}['k'][1]['n']"#, &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval(r#"{"b": 3} == $a"#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval(r#"{"a": 1} == {"a": 1.0}"#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval(r#"{"a": 1} == {"b": 1}"#, &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("{} != $a", &ctx), Ok(Value::Boolean(true)));
+    let dotted = eval("$user.address.street", &ctx).unwrap_err();
+    let keyed = eval("$user.address['street']", &ctx).unwrap_err();
+    assert_eq!(dotted.to_string(), "missing field street on $user.address at 1:1");
+    assert_eq!(keyed.to_string(), dotted.to_string());
+    assert!(matches!(eval("$user.address.city['x']", &ctx), Err(EvalError::TypeMismatch { expected: "map", found: "string", .. })));
+    assert!(matches!(eval("$user.address.city.x", &ctx), Err(EvalError::TypeMismatch { expected: "map", found: "string", .. })));
+    assert!(matches!(eval("$headers[0]", &ctx), Err(EvalError::TypeMismatch { expected: "string", found: "integer", .. })));
+    assert!(matches!(eval(r#"{"a": x / 0}"#, &ctx), Err(EvalError::DivisionByZero { .. })));
+}
+
+#[test]
+fn fields() {
+    let mut ctx = context();
+    let user = |name: &'static str| {
+        let mut user = HashMap::new();
+        user.insert("name".to_string(), name);
+        user
+    };
+    ctx.set("users", vec![user("ann"), user("bob")]);
+    ctx.register("users.by", 1, |args| Ok(Value::from(vec![args[0].clone()])));
+    assert_eq!(eval("$users[1].name", &ctx), Ok(Value::from("bob")));
+    assert_eq!(eval("$users[0].name == $users[0]['name']", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval(r#"{"a": {"b": 1}}["a"].b"#, &ctx), Ok(Value::Integer(1)));
+    assert_eq!(eval(r#"[{"x": 2}][0].x * x"#, &ctx), Ok(Value::Integer(14)));
+    assert_eq!(eval(r#"users.by({"id": 5})[0].id"#, &ctx), Ok(Value::Integer(5)));
+    let err = eval("$users[0].age", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "missing field age on $users[0] at 1:1");
+    assert_eq!(err.to_string(), eval("$users[0]['age']", &ctx).unwrap_err().to_string());
+    assert!(matches!(eval("$users[0].name.x", &ctx), Err(EvalError::TypeMismatch { expected: "map", found: "string", .. })));
+    assert!(matches!(eval("[1][0].x", &ctx), Err(EvalError::TypeMismatch { expected: "map", found: "integer", .. })));
+}
+
+#[test]
+fn membership() {
+    let mut ctx = context();
+    ctx.set("country", "IT");
+    ctx.set("user.roles", vec!["admin", "dev"]);
+    ctx.set("user.name", "Marco");
+    ctx.set("ids", vec![Value::from(1), Value::from(2.5), Value::Null]);
+    assert_eq!(eval(r#"$country in ["IT", "FR"]"#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval(r#""admin" in $user.roles"#, &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'root' not in $user.roles", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'roles' in $user && 'age' not in $user", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'arc' in $user.name", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'' in ''", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'MARCO' in $user.name", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("1.0 in $ids && 5 / 2 not in $ids", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("2.5 in $ids && null in $ids", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("'1' in $ids || [1] in $ids", &ctx), Ok(Value::Boolean(false)));
+    assert_eq!(eval("[1] in [[1], [2]]", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("[1] in [[1.0], [2]]", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("x in []", &ctx), Ok(Value::Boolean(false)));
+    assert!(matches!(eval("1 in $user", &ctx), Err(EvalError::TypeMismatch { expected: "string", found: "integer", .. })));
+    assert!(matches!(eval("null in 'null'", &ctx), Err(EvalError::TypeMismatch { expected: "string", found: "null", .. })));
+    let err = eval("1 in x", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "expected list, map or string, found integer at 1:6");
+}
+
+#[test]
+fn bitwise() {
+    let mut ctx = context();
+    ctx.set("flags", 0b0110);
+    assert_eq!(eval("$flags & 4 != 0", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$flags & 1 == 0 && flag", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$flags | 1", &ctx), Ok(Value::Integer(7)));
+    assert_eq!(eval("$flags ^ 3", &ctx), Ok(Value::Integer(5)));
+    assert_eq!(eval("~$flags", &ctx), Ok(Value::Integer(-7)));
+    assert_eq!(eval("1 << 4 | 1", &ctx), Ok(Value::Integer(17)));
+    assert_eq!(eval("-16 >> 2", &ctx), Ok(Value::Integer(-4)));
+    assert_eq!(eval("-1 << 63", &ctx), Ok(Value::Integer(i64::MIN)));
+    assert_eq!(eval("-3 << 2", &ctx), Ok(Value::Integer(-12)));
+    assert_eq!(eval("x & 3 + 1", &ctx), Ok(Value::Integer(4)));
+    assert!(matches!(eval("1 << 64", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("1 << 63", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("1 << 62 << 1", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("4611686018427387904 << 1", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("1 >> -1", &ctx), Err(EvalError::Overflow { .. })));
+    assert!(matches!(eval("1.0 & 1", &ctx), Err(EvalError::TypeMismatch { expected: "integer", found: "float", .. })));
+    assert!(matches!(eval("1 | flag", &ctx), Err(EvalError::TypeMismatch { expected: "integer", found: "boolean", .. })));
+    assert!(matches!(eval("~'a'", &ctx), Err(EvalError::TypeMismatch { expected: "integer", found: "string", .. })));
+    let err = eval("flag & flag", &ctx).unwrap_err();
+    assert_eq!(err.to_string(), "expected integer, found boolean at 1:1");
+}
+
+#[test]
+fn radix_literals() {
+    let mut ctx = context();
+    ctx.set("flags", 0x24);
+    assert_eq!(eval("$flags & 0x04 != 0", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("$flags == 0b10_0100 && $flags == 0o44", &ctx), Ok(Value::Boolean(true)));
+    assert_eq!(eval("1_000_000 / 1_000", &ctx), Ok(Value::Integer(1000)));
+    assert_eq!(eval("-0x7FFF_FFFF_FFFF_FFFF - 1", &ctx), Ok(Value::Integer(i64::MIN)));
+    assert!(matches!(eval("0x7FFF_FFFF_FFFF_FFFF + 1", &ctx), Err(EvalError::Overflow { .. })));
+}
@@ -63,3 +63,27 @@ fn read() {
     assert_eq!(s2.peek_many(6), "World!");
 }
 
+#[test]
+fn position() {
+    let s1 = State::from_string("ab\ncd");
+    assert_eq!((s1.offset(), s1.line(), s1.column()), (0, 1, 1));
+    let s2 = s1.read(2);
+    assert_eq!((s2.offset(), s2.line(), s2.column()), (2, 1, 3));
+    let s3 = s2.read(2);
+    assert_eq!((s3.offset(), s3.line(), s3.column()), (4, 2, 2));
+    assert_eq!(s1.slice_to(&s3), "ab\nc");
+}
+
+#[test]
+fn unicode() {
+    let s1 = State::from_string("é > ü");
+    assert_eq!(s1.peek(), 'é');
+    assert_eq!(s1.peek_many(2), "é ");
+    let s2 = s1.read(1);
+    assert_eq!(s2.peek(), ' ');
+    assert_eq!((s2.offset(), s2.column()), (2, 2));
+    let s3 = s2.read(3);
+    assert_eq!(s3.peek(), 'ü');
+    assert_eq!(s3.peek_many(2), "");
+    assert!(s3.read(5).complete());
+}